@@ -0,0 +1,674 @@
+//! What a blueprint makes: its machines, the beacons reaching them, and the items they
+//! produce and consume per second when running flat out.
+
+use crate::blueprint::{Blueprint, Direction, Entity};
+use crate::entity::{Beacon, CraftingMachine, MachineMap};
+use crate::logistics::LogisticsPrototypes;
+use crate::module::ModuleEffect;
+use crate::planner::{Planner, ProductionPlan};
+use crate::recipe::ProductId;
+use std::collections::BTreeMap;
+
+/// Rates below this are rounding noise rather than production or consumption.
+const RATE_EPSILON: f64 = 1e-9;
+
+/// A crafting machine found in a blueprint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlueprintMachine {
+    pub entity_number: u32,
+    pub machine: String,
+    pub recipe: String,
+    /// Modules the machine runs with, leaving out those its recipe does not accept.
+    pub modules: Vec<String>,
+    /// Entity numbers of the beacons reaching the machine.
+    pub beacons: Vec<u32>,
+    /// Combined effect of the modules, beacons and the planner's productivity bonuses.
+    pub effect: ModuleEffect,
+    /// Crafts per second at full speed.
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BlueprintAnalysis {
+    pub machines: Vec<BlueprintMachine>,
+    /// Machines reached by each beacon, by the beacon's entity number.
+    pub beacon_coverage: BTreeMap<u32, usize>,
+    /// Items made beyond what the blueprint uses itself, per second.
+    pub production: BTreeMap<ProductId, f64>,
+    /// Items the blueprint needs brought in, per second.
+    pub consumption: BTreeMap<ProductId, f64>,
+    /// What keeps the blueprint from running as built, such as machines without a
+    /// recipe or modules that cannot go where they are placed.
+    pub problems: Vec<String>,
+}
+
+/// Width and height of an entity of `size` facing `direction`.
+fn rotated(size: (u32, u32), direction: Direction) -> (f64, f64) {
+    match direction {
+        Direction::East | Direction::West => (size.1 as f64, size.0 as f64),
+        _ => (size.0 as f64, size.1 as f64),
+    }
+}
+
+/// Whether the supply area of `beacon` overlaps the tiles of `machine`.
+fn reaches(beacon: &Entity, prototype: &Beacon, machine: &Entity, size: (u32, u32)) -> bool {
+    let (bw, bh) = rotated(prototype.size, beacon.direction);
+    let (mw, mh) = rotated(size, machine.direction);
+    let reach = prototype.supply_area_distance;
+    (beacon.position.x - machine.position.x).abs() < (bw + mw) / 2f64 + reach
+        && (beacon.position.y - machine.position.y).abs() < (bh + mh) / 2f64 + reach
+}
+
+/// Whether `point` lies on the tiles of `entity`, which is `size` before rotation.
+fn covers(entity: &Entity, size: (u32, u32), point: (f64, f64)) -> bool {
+    let (w, h) = rotated(size, entity.direction);
+    (point.0 - entity.position.x).abs() < w / 2f64 && (point.1 - entity.position.y).abs() < h / 2f64
+}
+
+/// Recipes of the crafting machines in `blueprint`, by entity number, with those of
+/// furnaces worked out as blueprints leave them out. A furnace smelts the recipe of its
+/// categories that takes what inserters feed it from other machines, or that makes what
+/// they take from it into other machines; failing both, the only recipe its categories
+/// have. A furnace fed by another furnace is worked out once that one is.
+fn machine_recipes(
+    blueprint: &Blueprint,
+    planner: &Planner,
+    machines: &MachineMap,
+    logistics: &LogisticsPrototypes,
+) -> BTreeMap<u32, String> {
+    let crafting: Vec<(&Entity, &CraftingMachine)> = blueprint
+        .entities
+        .iter()
+        .filter_map(|e| machines.0.get(&e.name).map(|m| (e, m)))
+        .filter(|(_, m)| m.type_ != "mining-drill")
+        .collect();
+    let mut recipes: BTreeMap<u32, String> = crafting
+        .iter()
+        .filter_map(|(e, _)| e.recipe.clone().map(|r| (e.entity_number, r)))
+        .collect();
+
+    // (from, to) entity numbers of the machines each inserter moves items between; a
+    // north facing inserter takes from the north and drops to the south
+    let machine_at = |point: (f64, f64)| {
+        crafting
+            .iter()
+            .find(|(e, m)| covers(e, m.size, point))
+            .map(|(e, _)| e.entity_number)
+    };
+    let moves: Vec<(u32, u32)> = blueprint
+        .entities
+        .iter()
+        .filter_map(|e| {
+            let inserter = logistics.inserters.get(&e.name)?;
+            let (dx, dy) = e.direction.offset();
+            let (x, y) = (e.position.x, e.position.y);
+            let (pickup, drop) = (
+                inserter.pickup_distance.round(),
+                inserter.insert_distance.round(),
+            );
+            Some((
+                machine_at((x + dx as f64 * pickup, y + dy as f64 * pickup))?,
+                machine_at((x - dx as f64 * drop, y - dy as f64 * drop))?,
+            ))
+        })
+        .collect();
+
+    loop {
+        let mut found = Vec::new();
+        for (furnace, machine) in crafting
+            .iter()
+            .filter(|(e, m)| m.type_ == "furnace" && !recipes.contains_key(&e.entity_number))
+        {
+            let number = furnace.entity_number;
+            let known = |n: &u32| recipes.get(n).and_then(|r| planner.recipes.get(r));
+            let fed: Vec<&str> = moves
+                .iter()
+                .filter(|(_, to)| *to == number)
+                .filter_map(|(from, _)| known(from))
+                .flat_map(|r| r.results.iter().map(|i| &*i.name))
+                .collect();
+            let taken: Vec<&str> = moves
+                .iter()
+                .filter(|(from, _)| *from == number)
+                .filter_map(|(_, to)| known(to))
+                .flat_map(|r| r.ingredients.iter().map(|i| &*i.name))
+                .collect();
+            let candidates: Vec<_> = planner
+                .recipes
+                .recipes()
+                .into_iter()
+                .filter(|r| machine.crafting_categories.contains(&r.category))
+                .collect();
+            let matching: Vec<_> = candidates
+                .iter()
+                .copied()
+                .filter(|r| {
+                    r.ingredients.iter().any(|i| fed.contains(&&*i.name))
+                        || r.results.iter().any(|i| taken.contains(&&*i.name))
+                })
+                .collect();
+            match (&matching[..], &candidates[..]) {
+                ([recipe], _) | ([], [recipe]) => found.push((number, recipe.name.clone())),
+                _ => {}
+            }
+        }
+        if found.is_empty() {
+            return recipes;
+        }
+        recipes.extend(found);
+    }
+}
+
+/// Module names in `entity`, one per module; other items, like fuel, are left out.
+fn entity_modules(planner: &Planner, entity: &Entity) -> Vec<String> {
+    entity
+        .items
+        .iter()
+        .filter(|(item, _)| planner.module_map.0.contains_key(*item))
+        .flat_map(|(item, &count)| std::iter::repeat_n(item.clone(), count as usize))
+        .collect()
+}
+
+/// Works out what `blueprint` makes, using the planner's recipes, modules, beacons and
+/// productivity bonuses, `machines` to recognise its crafting machines and the inserters
+/// of `logistics` to work out what its furnaces smelt.
+pub fn analyze(
+    blueprint: &Blueprint,
+    planner: &Planner,
+    machines: &MachineMap,
+    logistics: &LogisticsPrototypes,
+) -> BlueprintAnalysis {
+    let mut analysis = BlueprintAnalysis::default();
+    let recipes = machine_recipes(blueprint, planner, machines, logistics);
+
+    let mut beacons = Vec::new();
+    for entity in blueprint.entities.iter() {
+        if let Some(prototype) = planner.beacons.get(&entity.name) {
+            let mut effect = ModuleEffect::default();
+            let mut modules = entity_modules(planner, entity);
+            if modules.len() > prototype.module_slots as usize {
+                analysis.problems.push(format!(
+                    "{} #{} holds {} modules in {} slots",
+                    entity.name,
+                    entity.entity_number,
+                    modules.len(),
+                    prototype.module_slots
+                ));
+                // only as many as fit count
+                modules.truncate(prototype.module_slots as usize);
+            }
+            for module in modules {
+                let module = &planner.module_map.0[&module];
+                let allowed = prototype.allowed_effects.as_ref().is_none_or(|allowed| {
+                    module
+                        .effect
+                        .effect_names()
+                        .iter()
+                        .all(|e| allowed.iter().any(|a| a == e))
+                });
+                if allowed {
+                    effect = effect + module.effect;
+                } else {
+                    analysis.problems.push(format!(
+                        "{} #{} cannot hold {}",
+                        entity.name, entity.entity_number, module.name
+                    ));
+                }
+            }
+            beacons.push((
+                entity,
+                prototype,
+                effect * prototype.distribution_effectivity,
+            ));
+            analysis.beacon_coverage.insert(entity.entity_number, 0);
+        }
+    }
+
+    let mut flows = BTreeMap::<ProductId, f64>::new();
+    for entity in blueprint.entities.iter() {
+        let machine = match machines.0.get(&entity.name) {
+            Some(machine) if machine.type_ != "mining-drill" => machine,
+            _ => continue,
+        };
+        let name = format!("{} #{}", entity.name, entity.entity_number);
+        let recipe = match recipes
+            .get(&entity.entity_number)
+            .map(|r| (r, planner.recipes.get(r)))
+        {
+            Some((_, Some(recipe))) => recipe,
+            Some((recipe, None)) => {
+                analysis
+                    .problems
+                    .push(format!("{} has unknown recipe {}", name, recipe));
+                continue;
+            }
+            None if machine.type_ == "furnace" => {
+                analysis
+                    .problems
+                    .push(format!("{} is idle, as nothing shows what it smelts", name));
+                continue;
+            }
+            None => {
+                analysis.problems.push(format!("{} has no recipe", name));
+                continue;
+            }
+        };
+
+        let mut modules = entity_modules(planner, entity);
+        if modules.len() > machine.module_slots as usize {
+            analysis.problems.push(format!(
+                "{} holds {} modules in {} slots",
+                name,
+                modules.len(),
+                machine.module_slots
+            ));
+        }
+        modules.retain(|m| {
+            let allowed = planner.module_map.0[m].allows(&recipe.name);
+            if !allowed {
+                analysis
+                    .problems
+                    .push(format!("{} cannot use {} for {}", name, m, recipe.name));
+            }
+            allowed
+        });
+
+        let research = ModuleEffect {
+            productivity: planner
+                .productivity
+                .get(&recipe.name)
+                .copied()
+                .unwrap_or(0f64),
+            ..ModuleEffect::default()
+        };
+        let mut effect = modules
+            .iter()
+            .map(|m| planner.module_map.0[m].effect)
+            .fold(research, |a, b| a + b);
+        let mut reaching = Vec::new();
+        for (beacon, prototype, transmitted) in beacons.iter() {
+            if reaches(beacon, prototype, entity, machine.size) {
+                effect = effect + *transmitted;
+                reaching.push(beacon.entity_number);
+                *analysis
+                    .beacon_coverage
+                    .get_mut(&beacon.entity_number)
+                    .unwrap() += 1;
+            }
+        }
+        let effect = effect.clamped();
+        let rate = machine.crafting_speed * effect.speed_multiplier() / recipe.energy_required();
+
+        let mut machine_flows = BTreeMap::new();
+        for item in recipe.ingredients.iter().chain(recipe.results.iter()) {
+            machine_flows
+                .entry(&item.name)
+                .or_insert_with(|| rate * recipe.net_amount(&item.name, effect.productivity));
+        }
+        for (item, rate) in machine_flows {
+            *flows.entry(item.clone()).or_default() += rate;
+        }
+
+        analysis.machines.push(BlueprintMachine {
+            entity_number: entity.entity_number,
+            machine: machine.name.clone(),
+            recipe: recipe.name.clone(),
+            modules,
+            beacons: reaching,
+            effect,
+            rate,
+        });
+    }
+
+    for (item, rate) in flows {
+        if rate > RATE_EPSILON {
+            analysis.production.insert(item, rate);
+        } else if rate < -RATE_EPSILON {
+            analysis.consumption.insert(item, -rate);
+        }
+    }
+    analysis
+}
+
+impl BlueprintAnalysis {
+    /// Plans the blueprint's production from what it consumes, to compare with the
+    /// blueprint: the planner is free to pick its own recipes and machines.
+    pub fn plan(&self, planner: &Planner) -> Result<ProductionPlan, String> {
+        let mut planner = planner.clone();
+        planner.inputs.extend(self.consumption.keys().cloned());
+        let goals: Vec<(ProductId, f64)> = self
+            .production
+            .iter()
+            .map(|(item, rate)| (item.clone(), *rate))
+            .collect();
+        planner.solve(&goals)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_assembler() -> CraftingMachine {
+    use crate::entity::EnergySource;
+    CraftingMachine {
+        name: "assembling-machine".into(),
+        type_: "assembling-machine".into(),
+        crafting_categories: vec!["crafting".into()],
+        crafting_speed: 0.75,
+        module_slots: 2,
+        allowed_effects: None,
+        energy_usage: 150e3,
+        energy_source: EnergySource {
+            type_: "electric".into(),
+            drain: 5e3,
+            effectivity: 1.0,
+            emissions_per_minute: 3.0,
+        },
+        size: (3, 3),
+    }
+}
+
+#[test]
+fn analyze_blueprint() -> Result<(), String> {
+    use crate::blueprint::Position;
+    use crate::module::{Module, ModuleMap};
+    use crate::planner::test_recipe;
+    use crate::recipe::RecipeMap;
+    use serde_json::Map;
+    use std::collections::HashSet;
+    use std::iter::FromIterator;
+
+    let recipes = RecipeMap::new(vec![
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "engine-unit",
+            10.0,
+            &[("iron-gear-wheel", 1), ("iron-plate", 2)],
+            &[("engine-unit", 1)],
+        ),
+    ]);
+    let mut planner = Planner::new(&recipes);
+    planner.productivity.insert("engine-unit".into(), 0.1);
+    planner.module_map = ModuleMap::new(vec![
+        Module {
+            name: "speed-module".into(),
+            category: "speed".into(),
+            tier: 1,
+            effect: ModuleEffect {
+                speed: 0.5,
+                consumption: 0.7,
+                ..ModuleEffect::default()
+            },
+            limitation: None,
+        },
+        Module {
+            name: "productivity-module".into(),
+            category: "productivity".into(),
+            tier: 1,
+            effect: ModuleEffect {
+                speed: -0.15,
+                consumption: 0.4,
+                productivity: 0.1,
+                pollution: 0.05,
+            },
+            limitation: Some(HashSet::from_iter(["engine-unit".into()])),
+        },
+    ]);
+    planner.beacons.insert(
+        "beacon".into(),
+        Beacon {
+            name: "beacon".into(),
+            distribution_effectivity: 0.5,
+            module_slots: 2,
+            allowed_effects: Some(vec!["speed".into(), "consumption".into()]),
+            energy_usage: 480e3,
+            supply_area_distance: 3.0,
+            size: (3, 3),
+        },
+    );
+    let drill = CraftingMachine {
+        name: "electric-mining-drill".into(),
+        type_: "mining-drill".into(),
+        crafting_categories: vec!["basic-solid".into()],
+        ..test_assembler()
+    };
+    let machines = MachineMap::new(vec![test_assembler(), drill]);
+
+    let entity =
+        |number: u32, name: &str, x: f64, y: f64, recipe: Option<&str>, items: &[(&str, u32)]| {
+            Entity {
+                entity_number: number,
+                name: name.into(),
+                position: Position { x, y },
+                direction: Direction::North,
+                recipe: recipe.map(String::from),
+                items: items.iter().map(|&(i, n)| (i.to_string(), n)).collect(),
+                connections: BTreeMap::new(),
+                type_: None,
+                extra: Map::new(),
+            }
+        };
+    let mut blueprint = Blueprint::new(None, 0);
+    blueprint.entities = vec![
+        // one module too many, and one that gears may not use
+        entity(
+            1,
+            "assembling-machine",
+            1.5,
+            1.5,
+            Some("iron-gear-wheel"),
+            &[("speed-module", 2), ("productivity-module", 1)],
+        ),
+        entity(
+            2,
+            "assembling-machine",
+            7.5,
+            1.5,
+            Some("engine-unit"),
+            &[("productivity-module", 2)],
+        ),
+        // reaches both assemblers but not the ones further out, and only the first two
+        // of its modules count
+        entity(
+            3,
+            "beacon",
+            4.5,
+            1.5,
+            None,
+            &[("speed-module", 3), ("productivity-module", 1)],
+        ),
+        entity(4, "assembling-machine", 20.5, 1.5, Some("rocket-part"), &[]),
+        entity(5, "assembling-machine", 20.5, 10.5, None, &[]),
+        // drills have no recipe and make nothing the planner knows
+        entity(6, "electric-mining-drill", 1.5, 10.5, None, &[]),
+        entity(7, "transport-belt", 0.5, 6.5, None, &[]),
+        entity(8, "beacon", 30.5, 30.5, None, &[]),
+    ];
+    let analysis = analyze(
+        &blueprint,
+        &planner,
+        &machines,
+        &LogisticsPrototypes::default(),
+    );
+
+    assert_eq!(analysis.machines.len(), 2);
+    assert_eq!(
+        analysis.beacon_coverage,
+        BTreeMap::from_iter([(3, 2), (8, 0)])
+    );
+    assert_eq!(
+        analysis.problems,
+        vec![
+            "beacon #3 holds 4 modules in 2 slots",
+            "beacon #3 cannot hold productivity-module",
+            "assembling-machine #1 holds 3 modules in 2 slots",
+            "assembling-machine #1 cannot use productivity-module for iron-gear-wheel",
+            "assembling-machine #4 has unknown recipe rocket-part",
+            "assembling-machine #5 has no recipe",
+        ]
+    );
+
+    // two speed modules and the beacon's half of one: +125% speed
+    let gears = &analysis.machines[0];
+    assert_eq!(gears.modules, vec!["speed-module"; 2]);
+    assert_eq!(gears.beacons, vec![3]);
+    let gear_rate = 0.75 * 2.25 / 0.5;
+    assert!((gears.rate - gear_rate).abs() < 1e-9);
+
+    // modules and research add up to 30% productivity
+    let engines = &analysis.machines[1];
+    assert!((engines.effect.productivity - 0.3).abs() < 1e-9);
+    let engine_rate = 0.75 * 0.95 / 10.0;
+    assert!((engines.rate - engine_rate).abs() < 1e-9);
+
+    // engines take some of the gears, leaving the rest made
+    assert!((analysis.production["iron-gear-wheel"] - (gear_rate - engine_rate)).abs() < 1e-9);
+    assert!((analysis.production["engine-unit"] - engine_rate * 1.3).abs() < 1e-9);
+    assert!(
+        (analysis.consumption["iron-plate"] - (2.0 * gear_rate + 2.0 * engine_rate)).abs() < 1e-9
+    );
+
+    let plan = analysis.plan(&planner)?;
+    assert!(plan.raw_inputs.contains_key("iron-plate"));
+
+    // an empty blueprint makes nothing
+    let analysis = analyze(
+        &Blueprint::new(None, 0),
+        &planner,
+        &machines,
+        &LogisticsPrototypes::default(),
+    );
+    assert!(analysis.machines.is_empty() && analysis.problems.is_empty());
+    assert!(analysis.production.is_empty() && analysis.consumption.is_empty());
+    Ok(())
+}
+
+#[test]
+fn analyze_furnaces() -> Result<(), String> {
+    use crate::blueprint::Position;
+    use crate::layout::test_logistics;
+    use crate::planner::test_recipe;
+    use crate::recipe::{Recipe, RecipeMap};
+    use serde_json::Map;
+
+    let smelting = |recipe: Recipe| Recipe {
+        category: "smelting".into(),
+        ..recipe
+    };
+    let recipes = RecipeMap::new(vec![
+        smelting(test_recipe(
+            "iron-plate",
+            3.2,
+            &[("iron-ore", 1)],
+            &[("iron-plate", 1)],
+        )),
+        smelting(test_recipe(
+            "copper-plate",
+            3.2,
+            &[("copper-ore", 1)],
+            &[("copper-plate", 1)],
+        )),
+        smelting(test_recipe(
+            "steel-plate",
+            16.0,
+            &[("iron-plate", 5)],
+            &[("steel-plate", 1)],
+        )),
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+    ]);
+    let planner = Planner::new(&recipes);
+    let furnace = CraftingMachine {
+        name: "stone-furnace".into(),
+        type_: "furnace".into(),
+        crafting_categories: vec!["smelting".into()],
+        crafting_speed: 1.0,
+        module_slots: 0,
+        size: (2, 2),
+        ..test_assembler()
+    };
+    let machines = MachineMap::new(vec![furnace, test_assembler()]);
+
+    let entity =
+        |number: u32, name: &str, x: f64, y: f64, direction, recipe: Option<&str>| Entity {
+            entity_number: number,
+            name: name.into(),
+            position: Position { x, y },
+            direction,
+            recipe: recipe.map(String::from),
+            items: BTreeMap::new(),
+            connections: BTreeMap::new(),
+            type_: None,
+            extra: Map::new(),
+        };
+    let mut blueprint = Blueprint::new(None, 0);
+    blueprint.entities = vec![
+        entity(1, "stone-furnace", 1.0, 1.0, Direction::North, None),
+        // from the first furnace into the assembler on its east
+        entity(2, "inserter", 2.5, 0.5, Direction::West, None),
+        entity(
+            3,
+            "assembling-machine",
+            4.5,
+            1.5,
+            Direction::North,
+            Some("iron-gear-wheel"),
+        ),
+        // from the first furnace into the second one south of it
+        entity(4, "inserter", 0.5, 2.5, Direction::North, None),
+        entity(5, "stone-furnace", 1.0, 4.0, Direction::North, None),
+        // nothing in or out
+        entity(6, "stone-furnace", 10.0, 10.0, Direction::North, None),
+    ];
+    let analysis = analyze(&blueprint, &planner, &machines, &test_logistics());
+
+    // gears take iron plates, and the furnace fed iron plates makes steel
+    let smelted: Vec<(u32, &str)> = analysis
+        .machines
+        .iter()
+        .map(|m| (m.entity_number, &*m.recipe))
+        .collect();
+    assert_eq!(
+        smelted,
+        vec![
+            (1, "iron-plate"),
+            (3, "iron-gear-wheel"),
+            (5, "steel-plate")
+        ]
+    );
+    assert!((analysis.machines[0].rate - 1.0 / 3.2).abs() < 1e-9);
+    assert!((analysis.machines[2].rate - 1.0 / 16.0).abs() < 1e-9);
+    assert_eq!(
+        analysis.problems,
+        vec!["stone-furnace #6 is idle, as nothing shows what it smelts".to_string()]
+    );
+
+    // without inserters no furnace shows what it smelts
+    let analysis = analyze(
+        &blueprint,
+        &planner,
+        &machines,
+        &LogisticsPrototypes::default(),
+    );
+    assert_eq!(analysis.machines.len(), 1);
+    assert_eq!(analysis.problems.len(), 3);
+
+    // a furnace that can smelt one recipe only smelts that
+    let only_iron = RecipeMap::new(vec![recipes.get("iron-plate").unwrap().clone()]);
+    let analysis = analyze(
+        &blueprint,
+        &Planner::new(&only_iron),
+        &machines,
+        &LogisticsPrototypes::default(),
+    );
+    assert_eq!(analysis.machines.len(), 3);
+    assert!(analysis.problems[0].contains("unknown recipe iron-gear-wheel"));
+    Ok(())
+}
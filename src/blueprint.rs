@@ -0,0 +1,320 @@
+//! Factorio blueprint strings: a version byte, then base64 of the zlib-deflated JSON of a
+//! blueprint or blueprint book.
+//!
+//! Only the fields the planner works with are typed. The rest are kept as JSON in each
+//! struct's `extra`, so that decoding and encoding a string does not lose anything.
+
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::io::Read;
+
+/// The only version byte the game has used so far.
+pub const VERSION_BYTE: char = '0';
+
+/// Game version as stored in blueprints: 16 bits each of major, minor, patch and build.
+pub const fn game_version(major: u16, minor: u16, patch: u16, build: u16) -> u64 {
+    (major as u64) << 48 | (minor as u64) << 32 | (patch as u64) << 16 | build as u64
+}
+
+/// Version written into the blueprints we make; later versions of the game read them too.
+pub const BLUEPRINT_VERSION: u64 = game_version(1, 1, 0, 0);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Position {
+    pub x: f64,
+    pub y: f64,
+}
+
+/// Which way an entity faces. Blueprints number the eight directions clockwise from
+/// north, and leave out the direction of entities facing north.
+#[derive(
+    Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize,
+)]
+#[serde(try_from = "u8", into = "u8")]
+pub enum Direction {
+    #[default]
+    North,
+    NorthEast,
+    East,
+    SouthEast,
+    South,
+    SouthWest,
+    West,
+    NorthWest,
+}
+
+impl Direction {
+    fn is_north(&self) -> bool {
+        *self == Direction::North
+    }
+
+    /// Step to the next tile this way, with y growing southwards as in the game.
+    pub fn offset(self) -> (i32, i32) {
+        use Direction::*;
+        match self {
+            North => (0, -1),
+            NorthEast => (1, -1),
+            East => (1, 0),
+            SouthEast => (1, 1),
+            South => (0, 1),
+            SouthWest => (-1, 1),
+            West => (-1, 0),
+            NorthWest => (-1, -1),
+        }
+    }
+
+    pub fn opposite(self) -> Direction {
+        Direction::try_from((self as u8 + 4) % 8).unwrap()
+    }
+
+    /// A quarter turn right.
+    pub fn clockwise(self) -> Direction {
+        Direction::try_from((self as u8 + 2) % 8).unwrap()
+    }
+
+    /// A quarter turn left.
+    pub fn counter_clockwise(self) -> Direction {
+        Direction::try_from((self as u8 + 6) % 8).unwrap()
+    }
+}
+
+impl TryFrom<u8> for Direction {
+    type Error = String;
+    fn try_from(value: u8) -> Result<Self, String> {
+        use Direction::*;
+        [
+            North, NorthEast, East, SouthEast, South, SouthWest, West, NorthWest,
+        ]
+        .get(value as usize)
+        .copied()
+        .ok_or_else(|| format!("Invalid direction {}", value))
+    }
+}
+
+impl From<Direction> for u8 {
+    fn from(direction: Direction) -> u8 {
+        direction as u8
+    }
+}
+
+/// An entity a wire leads to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireTarget {
+    pub entity_id: u32,
+    /// Which of the target's connection points, for entities with two, like combinators.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub circuit_id: Option<u32>,
+}
+
+/// Wires attached to one connection point of an entity.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ConnectionPoint {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub red: Vec<WireTarget>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub green: Vec<WireTarget>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Entity {
+    /// Number of the entity within the blueprint, starting at 1.
+    pub entity_number: u32,
+    pub name: String,
+    /// Centre of the entity, in tiles.
+    pub position: Position,
+    #[serde(default, skip_serializing_if = "Direction::is_north")]
+    pub direction: Direction,
+    /// Recipe set in an assembling machine.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recipe: Option<String>,
+    /// Items inserted into the entity, e.g. modules, by name.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub items: BTreeMap<String, u32>,
+    /// Wires by connection point, numbered from "1".
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub connections: BTreeMap<String, ConnectionPoint>,
+    /// "input" or "output" for underground belts and loaders.
+    #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+    pub type_: Option<String>,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Tile {
+    pub name: String,
+    pub position: Position,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SignalId {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Icon {
+    pub signal: SignalId,
+    /// Slot of the icon, from 1 to 4.
+    pub index: u32,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Blueprint {
+    /// Always "blueprint".
+    pub item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub icons: Vec<Icon>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub entities: Vec<Entity>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tiles: Vec<Tile>,
+    /// Game version that made the blueprint, see `game_version`.
+    pub version: u64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Blueprint {
+    /// An empty blueprint made by `version` of the game.
+    pub fn new(label: Option<String>, version: u64) -> Self {
+        Blueprint {
+            item: "blueprint".into(),
+            label,
+            icons: Vec::new(),
+            entities: Vec::new(),
+            tiles: Vec::new(),
+            version,
+            extra: Map::new(),
+        }
+    }
+}
+
+/// A blueprint or book inside a book, with its slot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BookEntry {
+    pub index: u32,
+    #[serde(flatten)]
+    pub content: BlueprintString,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BlueprintBook {
+    /// Always "blueprint-book".
+    pub item: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub blueprints: Vec<BookEntry>,
+    #[serde(default)]
+    pub active_index: u32,
+    pub version: u64,
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+/// What a blueprint string holds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlueprintString {
+    Blueprint(Blueprint),
+    BlueprintBook(BlueprintBook),
+}
+
+impl BlueprintString {
+    pub fn decode(string: &str) -> Result<Self, Box<dyn Error>> {
+        let string = string.trim();
+        let mut chars = string.chars();
+        match chars.next() {
+            Some(VERSION_BYTE) => {}
+            Some(version) => {
+                return Err(format!("Unsupported blueprint string version {:?}", version).into())
+            }
+            None => return Err("Empty blueprint string".into()),
+        }
+        let compressed = base64::decode(chars.as_str())?;
+        let mut json = String::new();
+        ZlibDecoder::new(&compressed[..]).read_to_string(&mut json)?;
+        Ok(serde_json::from_str(&json)?)
+    }
+
+    pub fn encode(&self) -> Result<String, Box<dyn Error>> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+        serde_json::to_writer(&mut encoder, self)?;
+        let compressed = encoder.finish()?;
+        Ok(format!("{}{}", VERSION_BYTE, base64::encode(compressed)))
+    }
+
+    /// All blueprints, including those in books, depth first.
+    pub fn blueprints(&self) -> Vec<&Blueprint> {
+        match self {
+            BlueprintString::Blueprint(blueprint) => vec![blueprint],
+            BlueprintString::BlueprintBook(book) => book
+                .blueprints
+                .iter()
+                .flat_map(|entry| entry.content.blueprints())
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+const SAMPLE_BLUEPRINT: &str = "0eNqVU9uK4zAM/Rc920OT3mbKvO9HLKU4ibYVOLaRnc6W4n9fOelmFtoyOxBiJEvnSEfyFRo7YGByCXZXoNa7CLufV4h0dMYWX7oEhB1Qwh4UONOPFnunj2hYf5wQLWQF5Dr8Dbsq7xWgS5QIJ6jRuBzc0DfIEjCDmBixbyy5o+5NeyKHuhaK4KMke1fIBXDxslZwGU9hYWwpPKpAjRXGkhQDYqd73w1WIuuc1V0R9WcnLiIn8d0R1zdiPTF3JNzT9UaBCJXY20ODJ3MmzyWnJW4HSge562agX8QxHb4vZ5lEMmUs1WJRzD4YNqkwwTvkKcJNFY1dV+XH2P2rOYm1zPv8SILlzB57Y61GK2BMrQ5eZHuqxiTGf3LXT7hXM3di42LwnHSDNt3TLp8OYfUAdz3jDrKNfGQv5xfI98BqHpILQ4Ky0InsbZtv+CJAy5geCLWYUMe2vwyu/gbvp/2V4M8HqcAaqV18P2Q1othn5Dgm16/VavtWb9cb+VavOf8BM9xB4g==";
+
+#[cfg(test)]
+const SAMPLE_BOOK: &str = "0eNqVVGGPmzAM/StTPidToe3drdrXaT9iqlAAXxstJJFjulUV/31OaOm2gu5OQiA7znv2s81F1LaHgMZRVXv/U+wud08Uux8XYVwLv8VuJe8HKco03o0B0RyctslH5wBiJwxBJ6RwussWeqcOoFH9OgJYMcgbZDHspQBHhgyMUNk4V67vakAOmEB0jNDV1riD6nRzNA5UyRTBR77sXSJPOX7eSnHOX2ZBaEyYy0DmDGO6FANAqzrf9pYjy2GQD0mU90pcBCT2PRCXV2I1MreGucfjJylYKEJvqxqO+mQ8pjuNwaY3VPFZOwG9GoxUfVzO1AnSqS3FapXMLmjUlJjEVzGMEW7MKFddpBdC+7fmhq31sB/mJFhP7LHT1iqwDIamUcGzbItqjGK8k7tc4N5M3ITaxeCRVA2WHmnXi03YzOBuJ9yepxEP6Pn7BvIjsJya5EJPIg00GXud5is+C9Ag0IxQqxE1l/1mcHEL3o/zy8H3hZTCas6dfd95NCLbJ8CYL5cvxeb5S/m8feJn85LJbgv4/04vw37rAp3Fe9c1kucNfe3R6WaxlCKXspznTJ0q/6P+LfbT1ae5Jyeo7r+rZeQ//D+kgw==";
+
+#[test]
+fn blueprint_strings() -> Result<(), Box<dyn Error>> {
+    let decoded = BlueprintString::decode(SAMPLE_BLUEPRINT)?;
+    let blueprint = match &decoded {
+        BlueprintString::Blueprint(blueprint) => blueprint,
+        _ => panic!("expected a blueprint"),
+    };
+    assert_eq!(blueprint.label.as_deref(), Some("Gears"));
+    assert_eq!(blueprint.version, game_version(1, 1, 61, 0));
+    assert_eq!(blueprint.icons[0].signal.name, "iron-gear-wheel");
+    assert_eq!(blueprint.entities.len(), 5);
+    assert_eq!(blueprint.tiles.len(), 2);
+    let assembler = &blueprint.entities[0];
+    assert_eq!(assembler.recipe.as_deref(), Some("iron-gear-wheel"));
+    assert_eq!(assembler.items["speed-module"], 2);
+    assert_eq!(assembler.direction, Direction::North);
+    let inserter = &blueprint.entities[1];
+    assert_eq!(inserter.direction, Direction::West);
+    assert_eq!(inserter.connections["1"].red[0].entity_id, 3);
+    assert!(inserter.extra.contains_key("control_behavior"));
+    assert_eq!(blueprint.entities[4].type_.as_deref(), Some("input"));
+
+    // re-encoding keeps everything, though not necessarily the same bytes
+    for sample in [SAMPLE_BLUEPRINT, SAMPLE_BOOK].iter() {
+        let decoded = BlueprintString::decode(sample)?;
+        let encoded = decoded.encode()?;
+        assert!(encoded.starts_with(VERSION_BYTE));
+        assert_eq!(BlueprintString::decode(&encoded)?, decoded);
+    }
+    let book = BlueprintString::decode(SAMPLE_BOOK)?;
+    let blueprints = book.blueprints();
+    assert_eq!(blueprints.len(), 2);
+    assert_eq!(blueprints[0], blueprint);
+    assert_eq!(blueprints[1].entities[0].name, "stone-furnace");
+
+    // directions left out are north, and north is left out again
+    let json = serde_json::to_string(&blueprint.entities[0])?;
+    assert!(!json.contains("direction"));
+    assert!(serde_json::to_string(inserter)?.contains("\"direction\":6"));
+
+    assert!(BlueprintString::decode("1eNo=").is_err());
+    assert!(BlueprintString::decode("").is_err());
+    assert!(Direction::try_from(8).is_err());
+    assert_eq!(Direction::West.opposite(), Direction::East);
+    assert_eq!(Direction::South.offset(), (0, 1));
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use crate::costs::CostTable;
+use crate::export::Export;
+use crate::layout::LayoutConfig;
+use crate::logistics::LogisticsConfig;
+use crate::module::BeaconLayout;
+use crate::optimizer::ModuleOptimizer;
+use crate::planner::Objective;
+use crate::power::PowerPlant;
+use crate::quantize::Rounding;
+use crate::recipe::ProductId;
+use crate::sensitivity::{Change, Limits};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::iter::FromIterator;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Solver {
+    /// Optimize recipe choice with `Planner::solve`.
+    LinearProgram,
+    /// Use one fixed recipe per item with `Planner::solve_by_components`.
+    Components,
+}
+
+/// Planner settings, read from the RON file given on the command line, e.g.
+///
+/// ```ron
+/// (
+///     goals: [("utility-science-pack", 1.0), ("production-science-pack", 1.0)],
+///     machines: { "crafting": "assembling-machine-2" },
+/// )
+/// ```
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PlannerConfig {
+    /// Items to make and their rates, planned together so that they share intermediates.
+    pub goals: Vec<(ProductId, f64)>,
+    pub solver: Solver,
+    pub objective: Objective,
+    /// Cost of one unit of each raw item, see `Planner::raw_costs`.
+    pub raw_costs: HashMap<ProductId, f64>,
+    /// Items supplied from outside the plan, e.g. by another factory, instead of being made.
+    pub inputs: HashSet<ProductId>,
+    /// Items the plan may not produce; see `Planner::forbidden`.
+    pub forbidden: HashSet<ProductId>,
+    /// Recipe to use for an item, see `Planner::pinned`.
+    pub pinned: HashMap<ProductId, String>,
+    /// Recipes that may not be used.
+    pub blacklist: HashSet<String>,
+    /// Preferred machine for each crafting category; others default to the fastest.
+    pub machines: HashMap<String, String>,
+    /// Modules for each machine, keyed by recipe name or, for every recipe that can
+    /// use them, by crafting category.
+    pub modules: HashMap<String, Vec<String>>,
+    /// Beacons around each machine, keyed like `modules`.
+    pub beacons: HashMap<String, BeaconLayout>,
+    /// Mining productivity bonus from research, e.g. 0.2 after two levels.
+    pub mining_productivity: f64,
+    /// Fuel burnt by burner machines and boilers.
+    pub fuel: String,
+    /// Kind of power plant to size for the plan's electricity, if any.
+    pub power_plant: Option<PowerPlant>,
+    /// Choose modules and beacons automatically instead of using `modules` and `beacons`.
+    pub optimize: Option<ModuleOptimizer>,
+    /// Adjust the goals so that the plan can be built from whole machines.
+    pub rounding: Option<Rounding>,
+    /// Belts, inserters and pipes to check the plan's flows against, if any.
+    pub logistics: Option<LogisticsConfig>,
+    /// Supply and machines available, to find which of them limit the goals.
+    pub limits: Option<Limits>,
+    /// Changes to try against `limits`, reporting how much more could be made.
+    pub what_if: Vec<Change>,
+    /// Print the raw resources and crafting time of one of every item.
+    pub cost_table: Option<CostTable>,
+    /// Path of an HTML report of the plan to write, if any.
+    pub report: Option<String>,
+    /// Files to write the plan to as JSON, RON or CSV.
+    pub exports: Vec<Export>,
+    /// Path of a file holding a blueprint string, to report what it makes.
+    pub blueprint: Option<String>,
+    /// Lay out the plan's machines and write them as a blueprint string, if set.
+    pub layout: Option<LayoutConfig>,
+}
+
+impl Default for PlannerConfig {
+    fn default() -> Self {
+        PlannerConfig {
+            goals: vec![("spidertron".into(), 1f64)],
+            solver: Solver::LinearProgram,
+            objective: Objective::RawResources,
+            // offshore pumps make water effectively free
+            raw_costs: HashMap::from_iter([("water".into(), 0f64)]),
+            inputs: HashSet::new(),
+            forbidden: HashSet::new(),
+            pinned: HashMap::new(),
+            blacklist: HashSet::new(),
+            machines: HashMap::new(),
+            modules: HashMap::new(),
+            beacons: HashMap::new(),
+            optimize: None,
+            rounding: None,
+            logistics: None,
+            limits: None,
+            what_if: Vec::new(),
+            cost_table: None,
+            report: None,
+            exports: Vec::new(),
+            blueprint: None,
+            layout: None,
+            mining_productivity: 0f64,
+            fuel: "coal".into(),
+            power_plant: None,
+        }
+    }
+}
+
+impl PlannerConfig {
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        let data = std::fs::read_to_string(path)?;
+        Ok(ron::de::from_str(&data)?)
+    }
+}
+
+#[test]
+fn parse_config() -> Result<(), Box<dyn Error>> {
+    use crate::costs::CostColumn;
+    use crate::export::ExportFormat;
+    use crate::optimizer::LoadoutObjective;
+    let config: PlannerConfig = ron::de::from_str(
+        r#"(
+            goals: [("utility-science-pack", 1.0)],
+            solver: Components,
+            objective: Weighted([(RawResources, 1.0), (Power, 0.1)]),
+            blacklist: ["coal-liquefaction"],
+            inputs: ["plastic-bar"],
+            forbidden: ["solid-fuel"],
+            machines: { "crafting": "assembling-machine-2" },
+            modules: { "oil-processing": ["productivity-module-3", "speed-module-3"] },
+            beacons: {
+                "crafting": (beacon: "beacon", count: 8, modules: ["speed-module-3", "speed-module-3"]),
+            },
+            optimize: Some((objective: Power, beacon: Some("beacon"))),
+            mining_productivity: 0.2,
+            power_plant: Some(Solar),
+            rounding: Some(Belts(belt: "fast-transport-belt", count: 1.0)),
+            limits: Some((supply: { "iron-plate": 30.0 }, machines: { "assembling-machine-2": 12.0 })),
+            cost_table: Some((sort_by: Raw("iron-ore"), descending: true)),
+            report: Some("plan.html"),
+            exports: [(format: Csv, path: "plan.csv")],
+            blueprint: Some("smelting.txt"),
+            layout: Some((belt: "fast-transport-belt", path: "factory.txt")),
+            what_if: [Machines("assembling-machine-2", 1.0), MiningProductivity(0.1)],
+            logistics: Some((belt: "express-transport-belt", stack_inserter_capacity_bonus: 11)),
+        )"#,
+    )?;
+    assert_eq!(config.goals, vec![("utility-science-pack".into(), 1.0)]);
+    assert_eq!(config.solver, Solver::Components);
+    assert_eq!(
+        config.objective,
+        Objective::Weighted(vec![
+            (Objective::RawResources, 1.0),
+            (Objective::Power, 0.1)
+        ])
+    );
+    assert!(config.blacklist.contains("coal-liquefaction"));
+    assert!(config.inputs.contains("plastic-bar"));
+    assert!(config.forbidden.contains("solid-fuel"));
+    assert_eq!(config.machines["crafting"], "assembling-machine-2");
+    assert_eq!(config.raw_costs["water"], 0.0);
+    assert_eq!(config.modules["oil-processing"].len(), 2);
+    assert_eq!(config.beacons["crafting"].count, 8);
+    assert_eq!(config.beacons["crafting"].shared_by, 1.0);
+    assert_eq!(config.mining_productivity, 0.2);
+    assert_eq!(config.power_plant, Some(PowerPlant::Solar));
+    assert_eq!(config.fuel, "coal");
+    assert_eq!(
+        config.rounding,
+        Some(Rounding::Belts {
+            belt: "fast-transport-belt".into(),
+            count: 1.0
+        })
+    );
+    assert_eq!(config.limits.unwrap().supply["iron-plate"], 30.0);
+    assert_eq!(config.what_if[1], Change::MiningProductivity(0.1));
+    let cost_table = config.cost_table.unwrap();
+    assert_eq!(cost_table.sort_by, CostColumn::Raw("iron-ore".into()));
+    assert!(cost_table.descending);
+    assert_eq!(config.report.as_deref(), Some("plan.html"));
+    assert_eq!(config.exports[0].format, ExportFormat::Csv);
+    assert_eq!(config.blueprint.as_deref(), Some("smelting.txt"));
+    let layout = config.layout.unwrap();
+    assert_eq!(layout.belt, "fast-transport-belt");
+    assert_eq!(layout.pole, "small-electric-pole");
+    assert_eq!(layout.path, "factory.txt");
+    let logistics = config.logistics.unwrap();
+    assert_eq!(logistics.belt, "express-transport-belt");
+    assert_eq!(logistics.inserter, "fast-inserter");
+    assert_eq!(logistics.stack_inserter_capacity_bonus, 11);
+    let optimizer = config.optimize.unwrap();
+    assert_eq!(optimizer.objective, LoadoutObjective::Power);
+    assert_eq!(optimizer.beacon_counts, vec![4, 8, 12]);
+    Ok(())
+}
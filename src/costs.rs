@@ -0,0 +1,227 @@
+//! The raw resources and crafting time behind one of each item.
+
+use crate::planner::{Planner, ProductionPlan};
+use crate::recipe::ProductId;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Write};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ItemCost {
+    pub item: ProductId,
+    /// Raw resources used per item, mined ore included.
+    pub raw: BTreeMap<ProductId, f64>,
+    /// Machine seconds spent crafting one item, with the planner's machines, modules and
+    /// bonuses.
+    pub crafting_time: f64,
+}
+
+impl ItemCost {
+    /// All raw resources summed, unweighted.
+    pub fn total_raw(&self) -> f64 {
+        self.raw.values().sum()
+    }
+
+    fn column(&self, column: &CostColumn) -> f64 {
+        match column {
+            CostColumn::Item => 0f64,
+            CostColumn::CraftingTime => self.crafting_time,
+            CostColumn::TotalRaw => self.total_raw(),
+            CostColumn::Raw(item) => self.raw.get(item).copied().unwrap_or(0f64),
+        }
+    }
+}
+
+/// Column to sort a cost table by.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum CostColumn {
+    Item,
+    CraftingTime,
+    TotalRaw,
+    Raw(ProductId),
+}
+
+/// Settings for printing the cost of every item.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CostTable {
+    pub sort_by: CostColumn,
+    pub descending: bool,
+}
+
+impl Default for CostTable {
+    fn default() -> Self {
+        CostTable {
+            sort_by: CostColumn::Item,
+            descending: false,
+        }
+    }
+}
+
+/// Raw resources a plan consumes: its raw inputs and what its mining steps mine.
+fn plan_resources(planner: &Planner, plan: &ProductionPlan) -> BTreeMap<ProductId, f64> {
+    let mut raw = plan.raw_inputs.clone();
+    for step in plan
+        .steps
+        .iter()
+        .filter(|s| planner.mined.contains(&s.recipe.name))
+    {
+        for (item, rate) in ProductionPlan::step_flows(step) {
+            if rate > 0f64 {
+                *raw.entry(item.to_string()).or_default() += rate;
+            }
+        }
+    }
+    raw
+}
+
+/// Plans one of `item` per second and reads off what it takes.
+pub fn item_cost(planner: &Planner, item: &str) -> Result<ItemCost, String> {
+    let plan = planner.solve(&[(item.to_string(), 1f64)])?;
+    Ok(ItemCost {
+        item: item.to_string(),
+        raw: plan_resources(planner, &plan),
+        crafting_time: plan.steps.iter().map(|s| s.machine_count).sum(),
+    })
+}
+
+/// The cost of every item with a recipe, and why each item that cannot be planned, e.g.
+/// because it is forbidden, was left out.
+pub fn item_costs(planner: &Planner) -> (Vec<ItemCost>, Vec<(ProductId, String)>) {
+    let mut items: Vec<&ProductId> = planner.recipes.0.keys().collect();
+    items.sort();
+    let mut costs = Vec::new();
+    let mut failures = Vec::new();
+    for item in items {
+        match item_cost(planner, item) {
+            Ok(cost) => costs.push(cost),
+            Err(e) => failures.push((item.clone(), e)),
+        }
+    }
+    (costs, failures)
+}
+
+impl CostTable {
+    pub fn sort(&self, costs: &mut [ItemCost]) {
+        costs.sort_by(|a, b| {
+            let order = match self.sort_by {
+                CostColumn::Item => a.item.cmp(&b.item),
+                ref column => a
+                    .column(column)
+                    .total_cmp(&b.column(column))
+                    .then_with(|| a.item.cmp(&b.item)),
+            };
+            if self.descending {
+                order.reverse()
+            } else {
+                order
+            }
+        });
+    }
+
+    /// Writes `costs` as aligned columns: the item, its crafting time, its total raw
+    /// resources and then each raw resource any item needs.
+    pub fn write<W: Write>(&self, w: &mut W, costs: &[ItemCost]) -> io::Result<()> {
+        let resources: BTreeSet<&ProductId> = costs.iter().flat_map(|c| c.raw.keys()).collect();
+        let width = costs.iter().map(|c| c.item.len()).max().unwrap_or(4).max(4);
+        write!(
+            w,
+            "{:width$} {:>10} {:>10}",
+            "item",
+            "time",
+            "raw",
+            width = width
+        )?;
+        for resource in resources.iter() {
+            write!(w, " {:>12}", resource)?;
+        }
+        writeln!(w)?;
+        for cost in costs.iter() {
+            write!(
+                w,
+                "{:width$} {:>10.3} {:>10.3}",
+                cost.item,
+                cost.crafting_time,
+                cost.total_raw(),
+                width = width
+            )?;
+            for resource in resources.iter() {
+                write!(
+                    w,
+                    " {:>12.3}",
+                    cost.raw.get(*resource).copied().unwrap_or(0f64)
+                )?;
+            }
+            writeln!(w)?;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+fn cost_table() -> Result<(), String> {
+    use crate::planner::test_recipe;
+    use crate::recipe::RecipeMap;
+
+    let recipes = RecipeMap::new(vec![
+        test_recipe("iron-ore", 1.0, &[], &[("iron-ore", 1)]),
+        test_recipe("iron-plate", 3.2, &[("iron-ore", 1)], &[("iron-plate", 1)]),
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+    ]);
+    let mut planner = Planner::new(&recipes);
+    planner.mined.insert("iron-ore".into());
+
+    // mined ore counts as raw, though the plan has no raw inputs
+    let gear = item_cost(&planner, "iron-gear-wheel")?;
+    assert_eq!(gear.raw.len(), 1);
+    assert!((gear.raw["iron-ore"] - 2.0).abs() < 1e-9);
+    assert!((gear.crafting_time - (2.0 + 6.4 + 0.5)).abs() < 1e-9);
+    // an item without a recipe is its own raw resource
+    assert!(item_cost(&planner, "copper-cable").is_ok_and(|c| c.raw["copper-cable"] == 1.0));
+
+    // items that cannot be planned are reported rather than dropped
+    planner.forbidden.insert("iron-gear-wheel".into());
+    let (mut costs, failures) = item_costs(&planner);
+    assert_eq!(
+        costs.iter().map(|c| &*c.item).collect::<Vec<_>>(),
+        vec!["iron-ore", "iron-plate"]
+    );
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].0, "iron-gear-wheel");
+
+    let table = CostTable {
+        sort_by: CostColumn::CraftingTime,
+        descending: true,
+    };
+    table.sort(&mut costs);
+    assert_eq!(costs[0].item, "iron-plate");
+    // an item missing a resource column sorts as needing none of it
+    let table = CostTable {
+        sort_by: CostColumn::Raw("copper-ore".into()),
+        descending: false,
+    };
+    table.sort(&mut costs);
+    assert_eq!(costs[0].item, "iron-ore");
+    // a degenerate solve's NaN sorts after every number rather than panicking
+    costs[0].crafting_time = f64::NAN;
+    CostTable {
+        sort_by: CostColumn::CraftingTime,
+        descending: false,
+    }
+    .sort(&mut costs);
+    assert_eq!(costs[1].item, "iron-ore");
+
+    let mut out = Vec::new();
+    table.write(&mut out, &[]).map_err(|e| e.to_string())?;
+    let out = String::from_utf8(out).map_err(|e| e.to_string())?;
+    assert_eq!(
+        out.trim_end(),
+        format!("{:4} {:>10} {:>10}", "item", "time", "raw")
+    );
+    Ok(())
+}
@@ -0,0 +1,374 @@
+use crate::lua_parser::{LuaContext, LuaObject};
+use crate::recipe::ConversionExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+
+/// Files under the base mod that define the entities the planner cares about.
+pub const ENTITY_FILES: &[&str] = &[
+    "prototypes/entity/entities.lua",
+    "prototypes/entity/mining-drill.lua",
+    "prototypes/entity/resources.lua",
+    "prototypes/entity/transport-belts.lua",
+];
+
+pub const CRAFTING_MACHINE_TYPES: &[&str] = &[
+    "assembling-machine",
+    "furnace",
+    "rocket-silo",
+    "mining-drill",
+];
+
+/// Every prototype table whose `type` is one of `types` in the `data:extend` calls of `ctxs`.
+pub fn prototypes_of_type(ctxs: &[LuaContext], types: &[&str]) -> Vec<LuaObject> {
+    let mut prototypes = Vec::new();
+    for group in ctxs.iter().flat_map(|ctx| ctx.data_extends.iter()) {
+        if let LuaObject::Array(objs) = group.clone().simplify() {
+            for obj in objs {
+                if let LuaObject::Map(map) = &obj {
+                    if let Some(LuaObject::Str(type_)) = map.get("type") {
+                        if types.contains(&&**type_) {
+                            prototypes.push(obj);
+                        }
+                    }
+                }
+            }
+        }
+    }
+    prototypes
+}
+
+/// Converts an energy or power string such as `"480kW"` or `"5MJ"` to watts or joules.
+pub fn parse_energy(value: &str) -> Result<f64, String> {
+    let number_end = value
+        .find(|c: char| c.is_ascii_alphabetic())
+        .ok_or_else(|| format!("No unit in {:?}", value))?;
+    let (number, unit) = value.split_at(number_end);
+    let number: f64 = number
+        .trim()
+        .parse()
+        .map_err(|e| format!("{:?}: {}", value, e))?;
+    let scale = match unit.chars().next() {
+        Some('k') => 1e3,
+        Some('M') => 1e6,
+        Some('G') => 1e9,
+        Some('T') => 1e12,
+        _ => 1f64,
+    };
+    match unit.chars().last() {
+        Some('W') | Some('J') => Ok(number * scale),
+        _ => Err(format!("Unknown energy unit in {:?}", value)),
+    }
+}
+
+/// Reads `module_specification.module_slots`, which is absent for machines without slots.
+fn module_slots(conts: &mut HashMap<String, LuaObject>) -> i64 {
+    conts
+        .field::<HashMap<String, LuaObject>>("module_specification")
+        .and_then(|mut spec| spec.field("module_slots"))
+        .unwrap_or(0)
+}
+
+/// Tiles covered by an entity, from its `collision_box`.
+fn tile_size(conts: &mut HashMap<String, LuaObject>) -> (u32, u32) {
+    conts
+        .field::<((f64, f64), (f64, f64))>("collision_box")
+        .map(|((x0, y0), (x1, y1))| ((x1 - x0).ceil() as u32, (y1 - y0).ceil() as u32))
+        .unwrap_or((1, 1))
+}
+
+/// Reads an optional energy field such as `energy_usage`, in watts or joules.
+fn energy_field(conts: &mut HashMap<String, LuaObject>, name: &str) -> Result<f64, String> {
+    conts
+        .field::<String>(name)
+        .ok()
+        .map_or(Ok(0f64), |value| parse_energy(&value))
+}
+
+/// How an entity is powered, read from its `energy_source` table.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EnergySource {
+    /// `electric`, `burner`, `heat`, `fluid` or `void`.
+    pub type_: String,
+    /// Power drawn by electric entities even while idle, in watts.
+    pub drain: f64,
+    /// Fraction of a burner's fuel energy that does work.
+    pub effectivity: f64,
+    pub emissions_per_minute: f64,
+}
+
+impl Default for EnergySource {
+    fn default() -> Self {
+        EnergySource {
+            type_: "void".into(),
+            drain: 0f64,
+            effectivity: 1f64,
+            emissions_per_minute: 0f64,
+        }
+    }
+}
+
+impl EnergySource {
+    /// Reads the `energy_source` table of an entity using `energy_usage` watts. Electric
+    /// entities without an explicit drain idle at a thirtieth of their usage, as in game.
+    fn from_entity(
+        conts: &mut HashMap<String, LuaObject>,
+        energy_usage: f64,
+    ) -> Result<Self, String> {
+        let mut source: HashMap<String, LuaObject> = match conts.field("energy_source") {
+            Ok(source) => source,
+            Err(_) => return Ok(EnergySource::default()),
+        };
+        let type_: String = source.field("type")?;
+        let drain = match source.field::<String>("drain") {
+            Ok(drain) => parse_energy(&drain)?,
+            Err(_) if type_ == "electric" => energy_usage / 30f64,
+            Err(_) => 0f64,
+        };
+        Ok(EnergySource {
+            type_,
+            drain,
+            effectivity: source.field("effectivity").unwrap_or(1f64),
+            emissions_per_minute: source.field("emissions_per_minute").unwrap_or(0f64),
+        })
+    }
+
+    pub fn is_electric(&self) -> bool {
+        self.type_ == "electric"
+    }
+
+    pub fn is_burner(&self) -> bool {
+        self.type_ == "burner"
+    }
+}
+
+/// A machine that runs recipes. Mining drills are included, with their resource categories
+/// as crafting categories and their mining speed as crafting speed, to run the recipes of
+/// `Resource::recipe`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CraftingMachine {
+    pub name: String,
+    pub type_: String,
+    pub crafting_categories: Vec<String>,
+    pub crafting_speed: f64,
+    pub module_slots: i64,
+    /// Module effects the machine accepts; any effect if absent.
+    pub allowed_effects: Option<Vec<String>>,
+    /// Power drawn while crafting, in watts, not counting the drain.
+    pub energy_usage: f64,
+    pub energy_source: EnergySource,
+    /// Width and height in tiles.
+    pub size: (u32, u32),
+}
+
+impl TryFrom<LuaObject> for CraftingMachine {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        let type_: String = conts.field("type")?;
+        let energy_usage =
+            energy_field(&mut conts, "energy_usage").map_err(|e| format!("{}: {}", name, e))?;
+        let (categories, speed) = if type_ == "mining-drill" {
+            ("resource_categories", "mining_speed")
+        } else {
+            ("crafting_categories", "crafting_speed")
+        };
+        Ok(CraftingMachine {
+            type_,
+            crafting_categories: conts
+                .field(categories)
+                .map_err(|e| format!("{}: {}", name, e))?,
+            crafting_speed: conts.field(speed).map_err(|e| format!("{}: {}", name, e))?,
+            module_slots: module_slots(&mut conts),
+            allowed_effects: conts.field("allowed_effects").ok(),
+            energy_usage,
+            energy_source: EnergySource::from_entity(&mut conts, energy_usage)
+                .map_err(|e| format!("{}: {}", name, e))?,
+            size: tile_size(&mut conts),
+            name,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Beacon {
+    pub name: String,
+    /// Fraction of its modules' effects the beacon passes on to each machine in range.
+    pub distribution_effectivity: f64,
+    pub module_slots: i64,
+    pub allowed_effects: Option<Vec<String>>,
+    /// Power drawn, in watts.
+    pub energy_usage: f64,
+    /// Tiles the beacon reaches beyond its own edges.
+    pub supply_area_distance: f64,
+    /// Width and height in tiles.
+    pub size: (u32, u32),
+}
+
+impl TryFrom<LuaObject> for Beacon {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        let energy_usage: String = conts
+            .field("energy_usage")
+            .map_err(|e| format!("{}: {}", name, e))?;
+        Ok(Beacon {
+            distribution_effectivity: conts
+                .field("distribution_effectivity")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            module_slots: module_slots(&mut conts),
+            allowed_effects: conts.field("allowed_effects").ok(),
+            energy_usage: parse_energy(&energy_usage)?,
+            supply_area_distance: conts
+                .field("supply_area_distance")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            size: tile_size(&mut conts),
+            name,
+        })
+    }
+}
+
+impl Beacon {
+    pub fn from_contexts(ctxs: &[LuaContext]) -> Result<BTreeMap<String, Beacon>, String> {
+        prototypes_of_type(ctxs, &["beacon"])
+            .into_iter()
+            .map(|obj| Beacon::try_from(obj).map(|b| (b.name.clone(), b)))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MachineMap(pub BTreeMap<String, CraftingMachine>);
+
+impl MachineMap {
+    pub fn new(machines: Vec<CraftingMachine>) -> Self {
+        MachineMap(machines.into_iter().map(|m| (m.name.clone(), m)).collect())
+    }
+
+    pub fn from_contexts(ctxs: &[LuaContext]) -> Result<Self, String> {
+        let machines = prototypes_of_type(ctxs, CRAFTING_MACHINE_TYPES)
+            .into_iter()
+            .map(CraftingMachine::try_from)
+            .collect::<Result<_, _>>()?;
+        Ok(MachineMap::new(machines))
+    }
+
+    /// Picks the machine used for each crafting category: the preferred one if there is
+    /// one, otherwise the fastest machine that can craft it. Fails if a preferred machine
+    /// is unknown or cannot craft its category.
+    pub fn choose(
+        &self,
+        preferred: &HashMap<String, String>,
+    ) -> Result<HashMap<String, CraftingMachine>, String> {
+        for (category, name) in preferred.iter() {
+            let machine = self
+                .0
+                .get(name)
+                .ok_or_else(|| format!("Unknown machine {} chosen for {}", name, category))?;
+            if !machine.crafting_categories.contains(category) {
+                return Err(format!("{} cannot craft {}", name, category));
+            }
+        }
+        let mut chosen = HashMap::<String, CraftingMachine>::new();
+        for machine in self.0.values() {
+            for category in machine.crafting_categories.iter() {
+                let is_preferred = preferred.get(category) == Some(&machine.name);
+                let better = match chosen.get(category) {
+                    None => true,
+                    Some(current) => {
+                        is_preferred
+                            || (preferred.get(category) != Some(&current.name)
+                                && machine.crafting_speed > current.crafting_speed)
+                    }
+                };
+                if better {
+                    chosen.insert(category.clone(), machine.clone());
+                }
+            }
+        }
+        Ok(chosen)
+    }
+}
+
+#[test]
+fn parse_crafting_machines() -> Result<(), String> {
+    use std::iter::FromIterator;
+    let lua = r#"data:extend({
+        { type = "assembling-machine", name = "assembling-machine-1",
+          crafting_categories = { "crafting", "basic-crafting", "advanced-crafting" },
+          crafting_speed = 0.5, energy_usage = "75kW" },
+        { type = "assembling-machine", name = "assembling-machine-2",
+          crafting_categories = { "crafting", "basic-crafting", "advanced-crafting", "crafting-with-fluid" },
+          crafting_speed = 0.75, module_specification = { module_slots = 2 },
+          energy_usage = "150kW", collision_box = {{-1.2, -1.2}, {1.2, 1.2}},
+          energy_source = { type = "electric", emissions_per_minute = 3 } },
+        { type = "furnace", name = "stone-furnace",
+          crafting_categories = { "smelting" }, crafting_speed = 1, energy_usage = "90kW",
+          energy_source = { type = "burner", fuel_category = "chemical", effectivity = 1,
+                            emissions_per_minute = 2 } },
+        { type = "inserter", name = "inserter", rotation_speed = 0.014 }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let machines = MachineMap::from_contexts(&[ctx])?;
+    assert_eq!(machines.0.len(), 3);
+    assert_eq!(machines.0["assembling-machine-2"].module_slots, 2);
+    assert_eq!(machines.0["stone-furnace"].crafting_speed, 1.0);
+    assert_eq!(machines.0["assembling-machine-2"].energy_usage, 150e3);
+    let source = &machines.0["assembling-machine-2"].energy_source;
+    assert!(source.is_electric());
+    assert_eq!(source.drain, 5e3);
+    assert_eq!(source.emissions_per_minute, 3.0);
+    assert_eq!(machines.0["assembling-machine-2"].size, (3, 3));
+    assert_eq!(machines.0["stone-furnace"].size, (1, 1));
+    assert!(machines.0["stone-furnace"].energy_source.is_burner());
+    assert_eq!(machines.0["stone-furnace"].energy_source.drain, 0.0);
+
+    let chosen = machines.choose(&HashMap::new())?;
+    assert_eq!(chosen["crafting"].name, "assembling-machine-2");
+    assert_eq!(chosen["smelting"].name, "stone-furnace");
+
+    let preferred = HashMap::from_iter([("crafting".into(), "assembling-machine-1".into())]);
+    let chosen = machines.choose(&preferred)?;
+    assert_eq!(chosen["crafting"].name, "assembling-machine-1");
+    assert_eq!(chosen["advanced-crafting"].name, "assembling-machine-2");
+
+    let misspelt = HashMap::from_iter([("crafting".into(), "assembling-machine-9".into())]);
+    assert!(machines.choose(&misspelt).is_err());
+    let wrong = HashMap::from_iter([("smelting".into(), "assembling-machine-1".into())]);
+    assert_eq!(
+        machines.choose(&wrong).unwrap_err(),
+        "assembling-machine-1 cannot craft smelting"
+    );
+    Ok(())
+}
+
+#[test]
+fn parse_beacons() -> Result<(), String> {
+    let lua = r#"data:extend({
+        { type = "beacon", name = "beacon", energy_usage = "480kW",
+          distribution_effectivity = 0.5, supply_area_distance = 3,
+          module_specification = { module_slots = 2 },
+          allowed_effects = { "consumption", "speed", "pollution" } }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let beacons = Beacon::from_contexts(&[ctx])?;
+    let beacon = &beacons["beacon"];
+    assert_eq!(beacon.distribution_effectivity, 0.5);
+    assert_eq!(beacon.module_slots, 2);
+    assert_eq!(beacon.energy_usage, 480e3);
+    assert_eq!(beacon.supply_area_distance, 3.0);
+    assert_eq!(beacon.allowed_effects.as_ref().unwrap().len(), 3);
+
+    assert_eq!(parse_energy("2.5MW")?, 2.5e6);
+    assert_eq!(parse_energy("90kJ")?, 90e3);
+    assert!(parse_energy("12").is_err());
+    Ok(())
+}
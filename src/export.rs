@@ -0,0 +1,220 @@
+//! Production plans written as JSON, RON or CSV for other tools.
+//!
+//! Every export carries `SCHEMA_VERSION`, which goes up whenever a field is renamed,
+//! removed or changes meaning, so that scripts reading the files can refuse ones they do
+//! not understand instead of misreading them.
+
+use crate::planner::ProductionPlan;
+use crate::recipe::ProductId;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::io::Write;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ExportFormat {
+    Json,
+    Ron,
+    /// One row per step, raw input, byproduct and goal, for spreadsheets.
+    Csv,
+}
+
+/// A file to write the plan to.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Export {
+    pub format: ExportFormat,
+    pub path: String,
+}
+
+/// A plan as written to JSON and RON.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanFile {
+    pub schema_version: u32,
+    pub plan: ProductionPlan,
+}
+
+/// A row of the CSV export. Rows of every kind share the columns, leaving blank those
+/// that do not apply.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct CsvRow {
+    pub schema_version: u32,
+    /// "step", "raw", "byproduct" or "goal".
+    pub kind: String,
+    /// Recipe name for steps, item name otherwise.
+    pub name: String,
+    /// Crafts per second for steps, items per second otherwise.
+    pub rate: f64,
+    pub machine: Option<String>,
+    pub machine_count: Option<f64>,
+    /// Module names, separated by ';'; blank without modules, as CSV cannot tell an
+    /// empty list from a missing one.
+    pub modules: Option<String>,
+    pub beacon: Option<String>,
+    pub beacon_count: Option<f64>,
+    /// In watts.
+    pub electric_power: Option<f64>,
+    /// In watts.
+    pub burner_power: Option<f64>,
+    /// Per minute.
+    pub pollution: Option<f64>,
+}
+
+fn item_rows<'a>(
+    kind: &'a str,
+    rates: impl Iterator<Item = (&'a ProductId, &'a f64)> + 'a,
+) -> impl Iterator<Item = CsvRow> + 'a {
+    rates.map(move |(item, &rate)| CsvRow {
+        schema_version: SCHEMA_VERSION,
+        kind: kind.into(),
+        name: item.clone(),
+        rate,
+        ..CsvRow::default()
+    })
+}
+
+/// The rows of the CSV export of `plan`.
+pub fn csv_rows(plan: &ProductionPlan) -> Vec<CsvRow> {
+    let steps = plan.steps.iter().map(|step| CsvRow {
+        schema_version: SCHEMA_VERSION,
+        kind: "step".into(),
+        name: step.recipe.name.clone(),
+        rate: step.rate,
+        machine: step.machine.clone(),
+        machine_count: Some(step.machine_count),
+        modules: (!step.modules.is_empty()).then(|| step.modules.join(";")),
+        beacon: step.beacons.as_ref().map(|b| b.beacon.clone()),
+        beacon_count: Some(step.beacon_count),
+        electric_power: Some(step.electric_power),
+        burner_power: Some(step.burner_power),
+        pollution: Some(step.pollution),
+    });
+    steps
+        .chain(item_rows("raw", plan.raw_inputs.iter()))
+        .chain(item_rows("byproduct", plan.byproducts.iter()))
+        .chain(item_rows("goal", plan.goals.iter().map(|(g, r)| (g, r))))
+        .collect()
+}
+
+/// Writes `plan` in `format`.
+pub fn write_plan<W: Write>(
+    f: &mut W,
+    plan: &ProductionPlan,
+    format: ExportFormat,
+) -> Result<(), Box<dyn Error>> {
+    let file = PlanFile {
+        schema_version: SCHEMA_VERSION,
+        plan: plan.clone(),
+    };
+    match format {
+        ExportFormat::Json => {
+            serde_json::to_writer_pretty(&mut *f, &file)?;
+            writeln!(f)?;
+        }
+        ExportFormat::Ron => {
+            let pretty = ron::ser::PrettyConfig::default();
+            writeln!(f, "{}", ron::ser::to_string_pretty(&file, pretty)?)?;
+        }
+        ExportFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(f);
+            for row in csv_rows(plan) {
+                writer.serialize(row)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads a plan written by `write_plan` as JSON or RON, refusing other schema versions.
+pub fn read_plan(data: &str, format: ExportFormat) -> Result<ProductionPlan, Box<dyn Error>> {
+    #[derive(Deserialize)]
+    struct Version {
+        schema_version: u32,
+    }
+    let version: Version = match format {
+        ExportFormat::Json => serde_json::from_str(data)?,
+        ExportFormat::Ron => ron::de::from_str(data)?,
+        ExportFormat::Csv => return Err("Plans cannot be read back from CSV".into()),
+    };
+    if version.schema_version != SCHEMA_VERSION {
+        return Err(format!(
+            "Plan has schema version {}, but only version {} can be read",
+            version.schema_version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    let file: PlanFile = match format {
+        ExportFormat::Json => serde_json::from_str(data)?,
+        _ => ron::de::from_str(data)?,
+    };
+    Ok(file.plan)
+}
+
+#[test]
+fn export_plans() -> Result<(), Box<dyn Error>> {
+    use crate::planner::{test_recipe, Planner};
+    use crate::recipe::RecipeMap;
+
+    let recipes = RecipeMap::new(vec![test_recipe(
+        "separate",
+        1.0,
+        &[("ore", 3)],
+        &[("iron", 1), ("stone", 1)],
+    )]);
+    let planner = Planner::new(&recipes);
+    let plan = planner.solve(&[("iron".into(), 1.0 / 3.0)])?;
+    // a goal supplied raw makes a plan without steps
+    let empty = planner.solve(&[("ore".into(), 1.0)])?;
+
+    for &format in [ExportFormat::Json, ExportFormat::Ron].iter() {
+        for plan in [&plan, &empty] {
+            let mut out = Vec::new();
+            write_plan(&mut out, plan, format)?;
+            let data = String::from_utf8(out)?;
+            let read = read_plan(&data, format)?;
+            assert_eq!(read.goals, plan.goals);
+            assert_eq!(read.steps.len(), plan.steps.len());
+            assert_eq!(read.byproducts.len(), plan.byproducts.len());
+            // RON may round the last digit of floats like a third
+            for (rate, read_rate) in plan.raw_inputs.values().zip(read.raw_inputs.values()) {
+                assert!((rate - read_rate).abs() < 1e-9);
+            }
+        }
+        assert!(read_plan("not a plan", format).is_err());
+    }
+
+    let mut json = Vec::new();
+    write_plan(&mut json, &plan, ExportFormat::Json)?;
+    let mut value: serde_json::Value = serde_json::from_slice(&json)?;
+    value["schema_version"] = (SCHEMA_VERSION + 1).into();
+    let error = read_plan(&value.to_string(), ExportFormat::Json).unwrap_err();
+    assert!(error.to_string().contains("schema version 2"));
+    value.as_object_mut().unwrap().remove("schema_version");
+    assert!(read_plan(&value.to_string(), ExportFormat::Json).is_err());
+    assert!(read_plan("", ExportFormat::Csv).is_err());
+
+    let rows = |plan: &ProductionPlan| -> Result<Vec<CsvRow>, Box<dyn Error>> {
+        let mut out = Vec::new();
+        write_plan(&mut out, plan, ExportFormat::Csv)?;
+        let mut reader = csv::Reader::from_reader(&out[..]);
+        assert_eq!(
+            reader.headers()?.iter().take(3).collect::<Vec<_>>(),
+            vec!["schema_version", "kind", "name"]
+        );
+        Ok(reader.deserialize().collect::<Result<Vec<CsvRow>, _>>()?)
+    };
+    // a step, ore, stone and the goal
+    let kinds: Vec<String> = rows(&plan)?.into_iter().map(|r| r.kind).collect();
+    assert_eq!(kinds, vec!["step", "raw", "byproduct", "goal"]);
+    let step = &rows(&plan)?[0];
+    assert_eq!(step.machine, None);
+    assert_eq!(step.modules, None);
+    // counted at crafting speed 1 without a machine
+    assert!((step.machine_count.unwrap() - 1.0 / 3.0).abs() < 1e-9);
+    assert_eq!(step.beacon, None);
+    // a goal supplied raw is both
+    let kinds: Vec<String> = rows(&empty)?.into_iter().map(|r| r.kind).collect();
+    assert_eq!(kinds, vec!["raw", "goal"]);
+    Ok(())
+}
@@ -0,0 +1,1064 @@
+//! Placing the machines of a plan on a grid of tiles, and turning the placement into a
+//! blueprint.
+//!
+//! A `LayoutStrategy` decides where everything goes. `Layout::check` then makes sure the
+//! result would run: belts reach where they should, inserters have something on both
+//! sides, and everything that needs power gets it.
+
+use crate::blueprint::{Blueprint, Direction, Entity, Position};
+use crate::entity::Beacon;
+use crate::logistics::LogisticsPrototypes;
+use crate::planner::{Planner, ProductionPlan};
+use crate::recipe::{Ingredient, ProductId};
+use crate::router::Router;
+use serde::{Deserialize, Serialize};
+use serde_json::Map;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+
+/// A tile, as x growing eastwards and y growing southwards.
+pub type Tile = (i32, i32);
+
+/// The tile `tiles` away from `tile` in `direction`.
+pub fn step(tile: Tile, direction: Direction, tiles: i32) -> Tile {
+    let (dx, dy) = direction.offset();
+    (tile.0 + dx * tiles, tile.1 + dy * tiles)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    /// A crafting machine, and whether it runs on electricity.
+    Machine {
+        electric: bool,
+    },
+    Beacon,
+    /// An inserter moving items `reach` tiles from either side of it.
+    Inserter {
+        reach: i32,
+    },
+    Belt,
+    /// The entrance or the exit of an underground belt.
+    Underground {
+        input: bool,
+    },
+    /// Two tiles wide across the way it faces.
+    Splitter,
+    Pole,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Placed {
+    pub name: String,
+    pub kind: Kind,
+    /// Top left tile.
+    pub tile: Tile,
+    /// Width and height in tiles, as placed.
+    pub size: (u32, u32),
+    /// Way belts move items, or the side inserters pick items up from.
+    pub direction: Direction,
+    pub recipe: Option<String>,
+    /// Modules, by name.
+    pub items: BTreeMap<String, u32>,
+}
+
+impl Placed {
+    pub fn new(name: &str, kind: Kind, tile: Tile, direction: Direction) -> Self {
+        Placed {
+            name: name.into(),
+            kind,
+            tile,
+            size: (1, 1),
+            direction,
+            recipe: None,
+            items: BTreeMap::new(),
+        }
+    }
+
+    fn centre(&self) -> (f64, f64) {
+        (
+            self.tile.0 as f64 + self.size.0 as f64 / 2f64,
+            self.tile.1 as f64 + self.size.1 as f64 / 2f64,
+        )
+    }
+
+    pub fn tiles(&self) -> impl Iterator<Item = Tile> + '_ {
+        (0..self.size.1 as i32).flat_map(move |dy| {
+            (0..self.size.0 as i32).map(move |dx| (self.tile.0 + dx, self.tile.1 + dy))
+        })
+    }
+
+    fn needs_power(&self) -> bool {
+        matches!(
+            self.kind,
+            Kind::Machine { electric: true } | Kind::Beacon | Kind::Inserter { .. }
+        )
+    }
+
+    /// Whether items on the entity move on towards the next tile it faces.
+    pub fn moves_items(&self) -> bool {
+        matches!(
+            self.kind,
+            Kind::Belt | Kind::Underground { .. } | Kind::Splitter
+        )
+    }
+}
+
+/// Whether `beacon` reaches `machine`, as the game measures it: `supply_area_distance`
+/// tiles out from the beacon's edges, onto any tile of the machine.
+fn beacon_reaches(beacon: &Placed, prototype: &Beacon, machine: &Placed) -> bool {
+    let ((bx, by), (mx, my)) = (beacon.centre(), machine.centre());
+    let reach = prototype.supply_area_distance;
+    (bx - mx).abs() < (beacon.size.0 + machine.size.0) as f64 / 2f64 + reach
+        && (by - my).abs() < (beacon.size.1 + machine.size.1) as f64 / 2f64 + reach
+}
+
+/// The end of a belt left for the player to connect.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Port {
+    pub item: ProductId,
+    /// Tile next to the end of the belt, where the player's belt comes from or goes to.
+    pub tile: Tile,
+    /// Way items move through `tile`.
+    pub direction: Direction,
+    /// Whether items come into the layout here.
+    pub input: bool,
+}
+
+/// A belt route the layout promises: items put on the belt at `from` reach `to`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Connection {
+    pub item: ProductId,
+    pub from: Tile,
+    pub to: Tile,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Layout {
+    pub entities: Vec<Placed>,
+    occupied: HashMap<Tile, usize>,
+    pub ports: Vec<Port>,
+    pub connections: Vec<Connection>,
+    /// What the strategy left out, such as pipes.
+    pub notes: Vec<String>,
+}
+
+impl Layout {
+    /// Adds `entity`, unless it would overlap one already placed.
+    pub fn place(&mut self, entity: Placed) -> Result<(), String> {
+        if let Some(tile) = entity.tiles().find(|t| self.occupied.contains_key(t)) {
+            return Err(format!(
+                "{} at {:?} would overlap {} at {:?}",
+                entity.name, entity.tile, self.entities[self.occupied[&tile]].name, tile
+            ));
+        }
+        let index = self.entities.len();
+        for tile in entity.tiles() {
+            self.occupied.insert(tile, index);
+        }
+        self.entities.push(entity);
+        Ok(())
+    }
+
+    /// Takes away the entity covering `tile`, if any.
+    pub fn remove(&mut self, tile: Tile) -> Option<Placed> {
+        let index = self.occupied.get(&tile).copied()?;
+        let entity = self.entities.remove(index);
+        self.occupied = HashMap::new();
+        for (i, e) in self.entities.iter().enumerate() {
+            for tile in e.tiles() {
+                self.occupied.insert(tile, i);
+            }
+        }
+        Some(entity)
+    }
+
+    /// Top left and bottom right tiles of everything placed, if anything is.
+    pub fn bounds(&self) -> Option<(Tile, Tile)> {
+        let xs = self.occupied.keys().map(|t| t.0);
+        let ys = self.occupied.keys().map(|t| t.1);
+        Some((
+            (xs.clone().min()?, ys.clone().min()?),
+            (xs.max()?, ys.max()?),
+        ))
+    }
+
+    /// The entity covering `tile`, if any.
+    pub fn at(&self, tile: Tile) -> Option<&Placed> {
+        self.occupied.get(&tile).map(|&i| &self.entities[i])
+    }
+
+    /// The exit an underground belt entrance leads to: the nearest exit of the same kind
+    /// facing the same way, within the belt's `max_distance`.
+    fn underground_exit(&self, entrance: &Placed, logistics: &LogisticsPrototypes) -> Option<Tile> {
+        let max_distance = logistics.undergrounds.get(&entrance.name)?.max_distance;
+        (1..=max_distance as i32)
+            .map(|k| step(entrance.tile, entrance.direction, k))
+            .find(|&t| {
+                self.at(t).is_some_and(|e| {
+                    e.name == entrance.name
+                        && e.direction == entrance.direction
+                        && e.kind == Kind::Underground { input: false }
+                })
+            })
+    }
+
+    /// The tiles items put on the belt at `tile` pass, in order, until the belt ends.
+    pub fn follow(&self, tile: Tile, logistics: &LogisticsPrototypes) -> Vec<Tile> {
+        let mut path = Vec::new();
+        let mut seen = HashSet::new();
+        let mut tile = tile;
+        while seen.insert(tile) {
+            let entity = match self.at(tile) {
+                Some(entity) if entity.moves_items() => entity,
+                _ => break,
+            };
+            path.push(tile);
+            tile = match entity.kind {
+                Kind::Underground { input: true } => {
+                    match self.underground_exit(entity, logistics) {
+                        Some(exit) => exit,
+                        None => break,
+                    }
+                }
+                _ => step(tile, entity.direction, 1),
+            };
+        }
+        path
+    }
+
+    /// What would keep the layout from running: broken belt connections, underground
+    /// belts without an exit, inserters with nothing to take from or give to, and
+    /// entities without power.
+    pub fn check(&self, logistics: &LogisticsPrototypes) -> Vec<String> {
+        let mut problems = Vec::new();
+        for connection in self.connections.iter() {
+            if !self
+                .follow(connection.from, logistics)
+                .contains(&connection.to)
+            {
+                problems.push(format!(
+                    "{} put on the belt at {:?} does not reach {:?}",
+                    connection.item, connection.from, connection.to
+                ));
+            }
+        }
+
+        let mut poles = Vec::new();
+        for entity in self.entities.iter() {
+            match entity.kind {
+                Kind::Underground { input: true }
+                    if self.underground_exit(entity, logistics).is_none() =>
+                {
+                    problems.push(format!("{} at {:?} has no exit", entity.name, entity.tile));
+                }
+                Kind::Inserter { reach } => {
+                    let pickup = step(entity.tile, entity.direction, reach);
+                    let drop = step(entity.tile, entity.direction.opposite(), reach);
+                    if self.at(pickup).is_none() {
+                        problems.push(format!(
+                            "{} at {:?} has nothing to take from",
+                            entity.name, entity.tile
+                        ));
+                    }
+                    if self.at(drop).is_none() {
+                        problems.push(format!(
+                            "{} at {:?} has nothing to give to",
+                            entity.name, entity.tile
+                        ));
+                    }
+                }
+                Kind::Pole => match logistics.poles.get(&entity.name) {
+                    Some(pole) => poles.push((entity.centre(), pole)),
+                    None => problems.push(format!("Unknown electric pole {}", entity.name)),
+                },
+                _ => {}
+            }
+        }
+
+        for entity in self.entities.iter().filter(|e| e.needs_power()) {
+            let (x, y) = entity.centre();
+            let (w, h) = (entity.size.0 as f64 / 2f64, entity.size.1 as f64 / 2f64);
+            let powered = poles.iter().any(|((px, py), pole)| {
+                (x - px).abs() < w + pole.supply_area_distance
+                    && (y - py).abs() < h + pole.supply_area_distance
+            });
+            if !powered {
+                problems.push(format!("{} at {:?} has no power", entity.name, entity.tile));
+            }
+        }
+
+        // poles within wire reach of each other share a network
+        let mut network = vec![usize::MAX; poles.len()];
+        let mut networks = 0;
+        for start in 0..poles.len() {
+            if network[start] != usize::MAX {
+                continue;
+            }
+            network[start] = networks;
+            let mut queue = VecDeque::from(vec![start]);
+            while let Some(i) = queue.pop_front() {
+                let ((x, y), pole) = poles[i];
+                for j in 0..poles.len() {
+                    let ((x2, y2), other) = poles[j];
+                    let reach = pole.maximum_wire_distance.min(other.maximum_wire_distance);
+                    if network[j] == usize::MAX && (x - x2).hypot(y - y2) <= reach {
+                        network[j] = networks;
+                        queue.push_back(j);
+                    }
+                }
+            }
+            networks += 1;
+        }
+        if networks > 1 {
+            problems.push(format!("Poles form {} separate networks", networks));
+        }
+        problems
+    }
+
+    /// The layout as a blueprint, with entities numbered in the order they were placed.
+    pub fn to_blueprint(&self, label: Option<String>, version: u64) -> Blueprint {
+        let mut blueprint = Blueprint::new(label, version);
+        blueprint.entities = self
+            .entities
+            .iter()
+            .enumerate()
+            .map(|(i, placed)| {
+                let (x, y) = placed.centre();
+                Entity {
+                    entity_number: i as u32 + 1,
+                    name: placed.name.clone(),
+                    position: Position { x, y },
+                    direction: placed.direction,
+                    recipe: placed.recipe.clone(),
+                    items: placed.items.clone(),
+                    connections: BTreeMap::new(),
+                    type_: match placed.kind {
+                        Kind::Underground { input } => {
+                            Some(if input { "input" } else { "output" }.into())
+                        }
+                        _ => None,
+                    },
+                    extra: Map::new(),
+                }
+            })
+            .collect();
+        blueprint
+    }
+}
+
+/// Entities to build with, by name.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LayoutConfig {
+    pub strategy: Strategy,
+    pub belt: String,
+    pub underground: String,
+    pub splitter: String,
+    pub inserter: String,
+    /// Inserter reaching over the nearer ingredient belt to the farther one.
+    pub long_inserter: String,
+    pub pole: String,
+    /// File to write the blueprint string to.
+    pub path: String,
+}
+
+impl Default for LayoutConfig {
+    fn default() -> Self {
+        LayoutConfig {
+            strategy: Strategy::Rows,
+            belt: "transport-belt".into(),
+            underground: "underground-belt".into(),
+            splitter: "splitter".into(),
+            inserter: "inserter".into(),
+            long_inserter: "long-handed-inserter".into(),
+            pole: "small-electric-pole".into(),
+            path: "layout.txt".into(),
+        }
+    }
+}
+
+/// A way of placing the steps of a plan.
+pub trait LayoutStrategy {
+    fn place(
+        &self,
+        plan: &ProductionPlan,
+        planner: &Planner,
+        logistics: &LogisticsPrototypes,
+        config: &LayoutConfig,
+    ) -> Result<Layout, String>;
+}
+
+/// The layout strategies to choose from in the config.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Strategy {
+    /// See `RowStrategy`.
+    Rows,
+}
+
+impl Strategy {
+    pub fn layout_strategy(&self) -> Box<dyn LayoutStrategy> {
+        match self {
+            Strategy::Rows => Box::new(RowStrategy),
+        }
+    }
+}
+
+/// One row of machines per step, built as many times as the step needs, from top to
+/// bottom:
+///
+/// - beacons, if the step uses them and they reach the machines over the belts
+/// - a belt running west for each solid ingredient, up to two
+/// - poles and inserters into the machines, long ones for the farther belt
+/// - the machines
+/// - poles and inserters out of the machines
+/// - a belt running east for the solid results
+/// - beacons again, if they reach the machines from there
+///
+/// with a row of poles above, between and below the steps. Only as many beacons are
+/// placed as give each machine the plan's count, and none that reach another row, so
+/// that the machines run as fast as planned; a row that cannot get them all gets a note
+/// instead. All belts end on the east
+/// side, where the `Router` joins each ingredient belt to the rows making the
+/// ingredient, splitting a row's results between the rows taking them. A row with
+/// several solid results puts them all on its one belt, which would jam a row taking
+/// only some of them, so its belt end is left as a port like every belt end the router
+/// cannot join.
+pub struct RowStrategy;
+
+/// Where a row's belts are.
+struct Row {
+    width: i32,
+    recipe: String,
+    inputs: Vec<(ProductId, i32)>,
+    output: Option<(Vec<ProductId>, i32)>,
+}
+
+impl RowStrategy {
+    /// Lays belts along row `y` across a row `width` wide, moving items `direction`.
+    fn belt_run(
+        layout: &mut Layout,
+        config: &LayoutConfig,
+        y: i32,
+        width: i32,
+        direction: Direction,
+    ) -> Result<(), String> {
+        for x in 0..width {
+            layout.place(Placed::new(&config.belt, Kind::Belt, (x, y), direction))?;
+        }
+        Ok(())
+    }
+
+    /// Picks beacons out of `candidates` until each of `machines` is reached by `wanted`
+    /// of them, preferring those reaching the most machines still short, and never one
+    /// reaching a machine that has enough or any of `others`, as those would run faster
+    /// than planned. Returns the beacons picked and the fewest any machine got.
+    fn pick_beacons(
+        mut candidates: Vec<Placed>,
+        prototype: &Beacon,
+        machines: &[Placed],
+        others: &[Placed],
+        wanted: usize,
+    ) -> (Vec<Placed>, usize) {
+        candidates.retain(|b| !others.iter().any(|m| beacon_reaches(b, prototype, m)));
+        let mut reached = vec![0; machines.len()];
+        let mut picked = Vec::new();
+        for i in 0..machines.len() {
+            while reached[i] < wanted {
+                let short = |b: &Placed| {
+                    machines
+                        .iter()
+                        .zip(reached.iter())
+                        .filter(|&(m, &n)| n < wanted && beacon_reaches(b, prototype, m))
+                        .count()
+                };
+                let best = candidates
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, b)| {
+                        beacon_reaches(b, prototype, &machines[i])
+                            && machines
+                                .iter()
+                                .zip(reached.iter())
+                                .all(|(m, &n)| n < wanted || !beacon_reaches(b, prototype, m))
+                    })
+                    .max_by_key(|&(k, b)| (short(b), Reverse(k)))
+                    .map(|(k, _)| k);
+                let beacon = match best {
+                    Some(k) => candidates.remove(k),
+                    None => break,
+                };
+                for (m, n) in machines.iter().zip(reached.iter_mut()) {
+                    if beacon_reaches(&beacon, prototype, m) {
+                        *n += 1;
+                    }
+                }
+                picked.push(beacon);
+            }
+        }
+        (picked, reached.into_iter().min().unwrap_or(wanted))
+    }
+
+    fn pole_row(
+        layout: &mut Layout,
+        config: &LayoutConfig,
+        y: i32,
+        width: i32,
+    ) -> Result<(), String> {
+        for x in (0..width.max(1)).step_by(3) {
+            layout.place(Placed::new(
+                &config.pole,
+                Kind::Pole,
+                (x, y),
+                Direction::North,
+            ))?;
+        }
+        Ok(())
+    }
+}
+
+impl LayoutStrategy for RowStrategy {
+    fn place(
+        &self,
+        plan: &ProductionPlan,
+        planner: &Planner,
+        logistics: &LogisticsPrototypes,
+        config: &LayoutConfig,
+    ) -> Result<Layout, String> {
+        let reach = |name: &str| {
+            logistics
+                .inserters
+                .get(name)
+                .map(|i| i.pickup_distance.round() as i32)
+                .ok_or_else(|| format!("Unknown inserter {}", name))
+        };
+        let (near, far) = (reach(&config.inserter)?, reach(&config.long_inserter)?);
+        if !logistics.poles.contains_key(&config.pole) {
+            return Err("Unknown electric pole in the layout config".into());
+        }
+        let router = Router::new(logistics, config)?;
+
+        let mut layout = Layout::default();
+        let mut rows: Vec<Row> = Vec::new();
+        let mut y = 1;
+        for step in plan.steps.iter() {
+            let recipe = &step.recipe;
+            let machine = match step
+                .machine
+                .as_ref()
+                .and_then(|name| planner.machines.values().find(|m| &m.name == name))
+            {
+                Some(machine) if machine.type_ != "mining-drill" => machine,
+                _ => {
+                    layout.notes.push(format!(
+                        "{} is left out, with no machine to place",
+                        recipe.name
+                    ));
+                    continue;
+                }
+            };
+            let (w, h) = (machine.size.0 as i32, machine.size.1 as i32);
+            if w < 3 || h < 3 {
+                layout.notes.push(format!(
+                    "{} is left out, as {} is too small for the row template",
+                    recipe.name, machine.name
+                ));
+                continue;
+            }
+            let mut solid = |list: &[Ingredient]| -> Vec<ProductId> {
+                let mut names: Vec<ProductId> = Vec::new();
+                for item in list.iter() {
+                    if item.type_ == "fluid" {
+                        layout.notes.push(format!(
+                            "Pipes for the {} of {} are not placed",
+                            item.name, recipe.name
+                        ));
+                    } else if !names.contains(&item.name) {
+                        names.push(item.name.clone());
+                    }
+                }
+                names
+            };
+            let mut inputs = solid(&recipe.ingredients);
+            let outputs = solid(&recipe.results);
+            if inputs.len() > 2 {
+                layout.notes.push(format!(
+                    "Only the first two solid ingredients of {} get belts",
+                    recipe.name
+                ));
+                inputs.truncate(2);
+            }
+            let count = step.built_machines().max(1f64) as i32;
+            let width = count * w;
+            let beacon = step
+                .beacons
+                .as_ref()
+                .and_then(|b| planner.beacons.get(&b.beacon).map(|p| (b, p)));
+            Self::pole_row(
+                &mut layout,
+                config,
+                y - 1,
+                width.max(rows.last().map_or(0, |r| r.width)),
+            )?;
+
+            // beacons go above the ingredient belts and below the output belt, each only
+            // if it is close enough there to reach the machines
+            let (top, bottom) = match beacon {
+                Some((_, prototype)) => {
+                    let output_gap = if outputs.is_empty() { 1 } else { 2 };
+                    (
+                        ((inputs.len() + 1) as f64) < prototype.supply_area_distance,
+                        (output_gap as f64) < prototype.supply_area_distance,
+                    )
+                }
+                None => (false, false),
+            };
+            let bh = beacon.map_or(0, |(_, p)| p.size.1 as i32);
+            let machine_at = |x: i32, y: i32| {
+                let mut placed = Placed::new(
+                    &machine.name,
+                    Kind::Machine {
+                        electric: machine.energy_source.is_electric(),
+                    },
+                    (x, y),
+                    Direction::North,
+                );
+                placed.size = machine.size;
+                placed
+            };
+            // start the row lower while the beacons of the row above would reach it
+            let machine_y = |y: i32| y + if top { bh } else { 0 } + inputs.len() as i32 + 1;
+            while (0..count).any(|i| {
+                let machine = machine_at(i * w, machine_y(y));
+                layout.entities.iter().any(|e| {
+                    planner
+                        .beacons
+                        .get(&e.name)
+                        .is_some_and(|p| e.kind == Kind::Beacon && beacon_reaches(e, p, &machine))
+                })
+            }) {
+                y += 1;
+            }
+            let others: Vec<Placed> = layout
+                .entities
+                .iter()
+                .filter(|e| matches!(e.kind, Kind::Machine { .. }))
+                .cloned()
+                .collect();
+            let mut machines = Vec::new();
+            let top_y = y;
+            if top {
+                y += bh;
+            }
+            let mut row = Row {
+                width,
+                recipe: recipe.name.clone(),
+                inputs: Vec::new(),
+                output: None,
+            };
+            for item in inputs.iter().rev() {
+                Self::belt_run(&mut layout, config, y, width, Direction::West)?;
+                row.inputs.push((item.clone(), y));
+                y += 1;
+            }
+            row.inputs.reverse();
+            for i in 0..count {
+                let x = i * w;
+                layout.place(Placed::new(
+                    &config.pole,
+                    Kind::Pole,
+                    (x, y),
+                    Direction::North,
+                ))?;
+                let inserters = [(&config.inserter, near), (&config.long_inserter, far)];
+                for (k, (name, reach)) in inserters.iter().take(inputs.len()).enumerate() {
+                    let inserter = Kind::Inserter { reach: *reach };
+                    layout.place(Placed::new(
+                        name,
+                        inserter,
+                        (x + 1 + k as i32, y),
+                        Direction::North,
+                    ))?;
+                }
+                let mut placed = machine_at(x, y + 1);
+                placed.recipe = Some(recipe.name.clone());
+                for module in step.modules.iter() {
+                    *placed.items.entry(module.clone()).or_default() += 1;
+                }
+                machines.push(placed.clone());
+                layout.place(placed)?;
+                layout.place(Placed::new(
+                    &config.pole,
+                    Kind::Pole,
+                    (x, y + 1 + h),
+                    Direction::North,
+                ))?;
+                if !outputs.is_empty() {
+                    let inserter = Kind::Inserter { reach: near };
+                    layout.place(Placed::new(
+                        &config.inserter,
+                        inserter,
+                        (x + 1, y + 1 + h),
+                        Direction::North,
+                    ))?;
+                }
+            }
+            y += h + 2;
+            if !outputs.is_empty() {
+                Self::belt_run(&mut layout, config, y, width, Direction::East)?;
+                row.output = Some((outputs, y));
+                y += 1;
+            }
+            if let Some((placement, prototype)) = beacon {
+                let bottom_y = y;
+                if bottom {
+                    y += bh;
+                }
+                let bw = prototype.size.0 as i32;
+                let candidates = [(top, top_y), (bottom, bottom_y)]
+                    .iter()
+                    .copied()
+                    .filter(|&(used, _)| used)
+                    .flat_map(|(_, by)| (0..(width / bw).max(1)).map(move |k| (k * bw, by)))
+                    .map(|tile| {
+                        let mut placed =
+                            Placed::new(&prototype.name, Kind::Beacon, tile, Direction::North);
+                        placed.size = prototype.size;
+                        for module in placement.modules.iter() {
+                            *placed.items.entry(module.clone()).or_default() += 1;
+                        }
+                        placed
+                    })
+                    .collect();
+                let wanted = placement.count.max(0) as usize;
+                let (picked, fewest) =
+                    Self::pick_beacons(candidates, prototype, &machines, &others, wanted);
+                if fewest < wanted {
+                    layout.notes.push(format!(
+                        "{} machines get {} of the {} beacons the plan has around each",
+                        recipe.name, fewest, wanted
+                    ));
+                }
+                let counted = placement.beacons_for(count as f64).ceil();
+                if picked.len() as f64 > counted {
+                    layout.notes.push(format!(
+                        "{} takes {} beacons where the plan counts {}",
+                        recipe.name,
+                        picked.len(),
+                        counted
+                    ));
+                }
+                for placed in picked {
+                    layout.place(placed)?;
+                }
+            }
+            rows.push(row);
+            y += 1;
+        }
+        if let Some(last) = rows.last() {
+            Self::pole_row(&mut layout, config, y - 1, last.width)?;
+        }
+
+        // the router joins each ingredient belt to the rows making the ingredient
+        for row in rows.iter() {
+            for (item, input_y) in row.inputs.iter() {
+                layout.ports.push(Port {
+                    item: item.clone(),
+                    tile: (row.width, *input_y),
+                    direction: Direction::West,
+                    input: true,
+                });
+            }
+        }
+        let output = |row: &Row, item: &ProductId, output_y: i32| Port {
+            item: item.clone(),
+            tile: (row.width, output_y),
+            direction: Direction::East,
+            input: false,
+        };
+        for row in rows.iter() {
+            if let Some((items, output_y)) = row.output.as_ref().filter(|(i, _)| i.len() == 1) {
+                layout.ports.push(output(row, &items[0], *output_y));
+            }
+        }
+        router.connect_ports(&mut layout);
+        for row in rows.iter() {
+            if let Some((items, output_y)) = row.output.as_ref().filter(|(i, _)| i.len() > 1) {
+                layout.notes.push(format!(
+                    "{} puts {} on one belt",
+                    row.recipe,
+                    items.join(" and ")
+                ));
+                for item in items.iter() {
+                    layout.ports.push(output(row, item, *output_y));
+                }
+            }
+        }
+        Ok(layout)
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_logistics() -> LogisticsPrototypes {
+    use crate::logistics::{ElectricPole, Inserter, Splitter, TransportBelt, UndergroundBelt};
+    let mut logistics = LogisticsPrototypes::default();
+    logistics.belts.insert(
+        "transport-belt".into(),
+        TransportBelt {
+            name: "transport-belt".into(),
+            speed: 0.03125,
+        },
+    );
+    logistics.undergrounds.insert(
+        "underground-belt".into(),
+        UndergroundBelt {
+            name: "underground-belt".into(),
+            max_distance: 5,
+        },
+    );
+    logistics.splitters.insert(
+        "splitter".into(),
+        Splitter {
+            name: "splitter".into(),
+            speed: 0.03125,
+        },
+    );
+    for (name, distance) in [("inserter", 1.0), ("long-handed-inserter", 2.0)] {
+        logistics.inserters.insert(
+            name.into(),
+            Inserter {
+                name: name.into(),
+                rotation_speed: 0.014,
+                extension_speed: 0.03,
+                stack: false,
+                pickup_distance: distance,
+                insert_distance: distance + 0.2,
+            },
+        );
+    }
+    logistics.poles.insert(
+        "small-electric-pole".into(),
+        ElectricPole {
+            name: "small-electric-pole".into(),
+            supply_area_distance: 2.5,
+            maximum_wire_distance: 7.5,
+        },
+    );
+    logistics
+}
+
+#[test]
+fn row_layout() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::analysis::{analyze, test_assembler};
+    use crate::blueprint::{BlueprintString, BLUEPRINT_VERSION};
+    use crate::entity::MachineMap;
+    use crate::module::BeaconLayout;
+    use crate::planner::{test_modules, test_recipe};
+    use crate::recipe::RecipeMap;
+
+    let recipes = RecipeMap::new(vec![
+        test_recipe(
+            "transport-belt",
+            0.5,
+            &[("iron-gear-wheel", 1), ("iron-plate", 1)],
+            &[("transport-belt", 2)],
+        ),
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "mechanism",
+            1.0,
+            &[("iron-gear-wheel", 1)],
+            &[("mechanism", 1)],
+        ),
+    ]);
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_assembler());
+    planner.module_map = test_modules();
+    planner.beacons.insert(
+        "beacon".into(),
+        Beacon {
+            name: "beacon".into(),
+            distribution_effectivity: 0.5,
+            module_slots: 2,
+            allowed_effects: None,
+            energy_usage: 480e3,
+            supply_area_distance: 3.0,
+            size: (3, 3),
+        },
+    );
+    // gears have beacons above and below, while belts, with two ingredient belts in the
+    // way, only have them below
+    for (recipe, count) in [("iron-gear-wheel", 2), ("transport-belt", 1)] {
+        planner.beacon_layouts.insert(
+            recipe.into(),
+            BeaconLayout {
+                beacon: "beacon".into(),
+                count,
+                modules: vec!["speed-module".into(); 2],
+                shared_by: 1.0,
+            },
+        );
+    }
+    let plan = planner.solve(&[("transport-belt".into(), 3.0), ("mechanism".into(), 0.75)])?;
+    let logistics = test_logistics();
+    let config = LayoutConfig::default();
+    let place = |plan: &ProductionPlan, planner: &Planner, config: &LayoutConfig| {
+        config
+            .strategy
+            .layout_strategy()
+            .place(plan, planner, &logistics, config)
+    };
+
+    let layout = place(&plan, &planner, &config)?;
+    assert_eq!(layout.check(&logistics), Vec::<String>::new());
+    // the gear row is split between the two rows taking gears, and plates come from
+    // outside
+    assert_eq!(layout.connections.len(), 2);
+    assert!(layout
+        .connections
+        .iter()
+        .all(|c| c.item == "iron-gear-wheel"));
+    assert!(layout.entities.iter().any(|e| e.kind == Kind::Splitter));
+    assert_eq!(layout.notes.len(), 2);
+    assert!(layout
+        .notes
+        .iter()
+        .all(|n| n.starts_with("Nothing in the layout puts out the iron-plate")));
+    let mut ports: Vec<(&str, bool)> = layout.ports.iter().map(|p| (&*p.item, p.input)).collect();
+    ports.sort();
+    assert_eq!(
+        ports,
+        vec![
+            ("iron-plate", true),
+            ("iron-plate", true),
+            ("mechanism", false),
+            ("transport-belt", false)
+        ]
+    );
+    assert!(layout
+        .entities
+        .iter()
+        .any(|e| e.kind == Kind::Inserter { reach: 2 }));
+
+    // the blueprint makes what the plan asks for, with machines to spare
+    let string = BlueprintString::Blueprint(layout.to_blueprint(None, BLUEPRINT_VERSION));
+    let decoded = BlueprintString::decode(&string.encode()?)?;
+    let analysis = analyze(
+        decoded.blueprints()[0],
+        &planner,
+        &MachineMap::new(vec![test_assembler()]),
+        &logistics,
+    );
+    assert!(analysis.problems.is_empty());
+    // each machine gets the beacons the plan has around it, and no more
+    for machine in analysis.machines.iter() {
+        let planned = planner
+            .beacon_layouts
+            .get(&machine.recipe)
+            .map_or(0, |b| b.count as usize);
+        assert_eq!(machine.beacons.len(), planned, "{}", machine.recipe);
+    }
+    assert!(analysis.production["transport-belt"] >= 3.0);
+    assert!(analysis.production["mechanism"] >= 0.75);
+    assert!(!analysis.consumption.contains_key("iron-gear-wheel"));
+
+    // taking out the poles of the first row and a belt breaks the layout
+    let mut broken = layout.clone();
+    let first_row = layout.connections[0].from.1.min(layout.connections[0].to.1);
+    for pole in layout
+        .entities
+        .iter()
+        .filter(|e| e.kind == Kind::Pole && e.tile.1 <= first_row)
+    {
+        broken.remove(pole.tile);
+    }
+    let connection = broken.connections[0].clone();
+    let path = broken.follow(connection.from, &logistics);
+    broken.remove(path[path.len() / 2]);
+    let problems = broken.check(&logistics);
+    assert!(problems.iter().any(|p| p.contains("does not reach")));
+    assert!(problems
+        .iter()
+        .any(|p| p.contains("no power") || p.contains("networks")));
+
+    // beacons that cannot all reach are left out with a note, rather than placed where
+    // they do nothing
+    let mut crowded = planner.clone();
+    crowded
+        .beacon_layouts
+        .get_mut("transport-belt")
+        .unwrap()
+        .count = 3;
+    let layout = place(
+        &crowded.solve(&[("transport-belt".into(), 3.0)])?,
+        &crowded,
+        &config,
+    )?;
+    assert!(layout.notes.contains(
+        &"transport-belt machines get 1 of the 3 beacons the plan has around each".to_string()
+    ));
+
+    // a goal supplied raw leaves nothing to place
+    let layout = place(
+        &planner.solve(&[("iron-plate".into(), 1.0)])?,
+        &planner,
+        &config,
+    )?;
+    assert!(layout.entities.is_empty() && layout.ports.is_empty() && layout.notes.is_empty());
+
+    // steps without a machine are left out
+    let unplaced = Planner::new(&recipes);
+    let layout = place(
+        &unplaced.solve(&[("transport-belt".into(), 2.0)])?,
+        &unplaced,
+        &config,
+    )?;
+    assert!(layout.entities.is_empty());
+    assert_eq!(layout.notes.len(), 2);
+    assert!(layout
+        .notes
+        .iter()
+        .all(|n| n.ends_with("no machine to place")));
+
+    // fluids get no pipes, and several results share one belt left as ports
+    let mut recycling = test_recipe(
+        "recycling",
+        1.0,
+        &[("scrap", 1), ("water", 10)],
+        &[("iron-gear-wheel", 1), ("copper-cable", 1)],
+    );
+    recycling.ingredients[1].type_ = "fluid".into();
+    let recipes = RecipeMap::new(vec![recycling]);
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_assembler());
+    let layout = place(
+        &planner.solve(&[("iron-gear-wheel".into(), 1.0)])?,
+        &planner,
+        &config,
+    )?;
+    assert!(layout.connections.is_empty());
+    assert_eq!(layout.ports.len(), 3);
+    assert_eq!(layout.ports.iter().filter(|p| p.input).count(), 1);
+    assert!(layout
+        .notes
+        .contains(&"Pipes for the water of recycling are not placed".to_string()));
+    assert!(layout
+        .notes
+        .contains(&"recycling puts iron-gear-wheel and copper-cable on one belt".to_string()));
+    assert!(layout
+        .notes
+        .iter()
+        .any(|n| n.starts_with("Nothing in the layout puts out the scrap")));
+
+    // the splitter must be one the router knows
+    let fast = LayoutConfig {
+        splitter: "fast-splitter".into(),
+        ..LayoutConfig::default()
+    };
+    assert!(place(&plan, &planner, &fast).is_err());
+    Ok(())
+}
@@ -0,0 +1,521 @@
+//! Checking that the items and fluids of a plan can be moved between its machines.
+
+use crate::entity::prototypes_of_type;
+use crate::lua_parser::{LuaContext, LuaObject};
+use crate::planner::ProductionPlan;
+use crate::recipe::{ConversionExt, ProductId};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+
+/// Items a belt moves per second for each unit of prototype `speed`, which is in tiles
+/// per tick: 60 ticks a second and 8 items per tile over both lanes.
+const BELT_ITEMS_PER_SPEED: f64 = 480f64;
+/// Fluid per second through a straight run of pipes of at least the given length.
+const PIPE_THROUGHPUT: &[(u32, f64)] = &[
+    (1, 6000f64),
+    (2, 3000f64),
+    (3, 3000f64),
+    (7, 2000f64),
+    (12, 1500f64),
+    (17, 1200f64),
+];
+/// Longer runs of pipe need pumps along them to keep the flow of the longest entry of
+/// `PIPE_THROUGHPUT`.
+const MAX_UNPUMPED_PIPES: u32 = 17;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TransportBelt {
+    pub name: String,
+    /// Tiles moved per tick.
+    pub speed: f64,
+}
+
+impl TryFrom<LuaObject> for TransportBelt {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        Ok(TransportBelt {
+            speed: conts
+                .field("speed")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            name,
+        })
+    }
+}
+
+impl TransportBelt {
+    /// Throughput of both lanes together.
+    pub fn items_per_second(&self) -> f64 {
+        self.speed * BELT_ITEMS_PER_SPEED
+    }
+
+    pub fn lane_items_per_second(&self) -> f64 {
+        self.items_per_second() / 2f64
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Inserter {
+    pub name: String,
+    /// Turns per tick.
+    pub rotation_speed: f64,
+    /// Tiles per tick.
+    pub extension_speed: f64,
+    /// Stack inserters get `stack_inserter_capacity_bonus` instead of
+    /// `inserter_stack_size_bonus`.
+    pub stack: bool,
+    /// Distances from the inserter's centre it picks items up from and drops them at.
+    pub pickup_distance: f64,
+    pub insert_distance: f64,
+}
+
+fn distance(conts: &mut HashMap<String, LuaObject>, name: &str) -> f64 {
+    conts
+        .field::<(f64, f64)>(name)
+        .map_or(1f64, |(x, y)| x.hypot(y))
+}
+
+impl TryFrom<LuaObject> for Inserter {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        Ok(Inserter {
+            rotation_speed: conts
+                .field("rotation_speed")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            extension_speed: conts
+                .field("extension_speed")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            stack: conts.field("stack").unwrap_or(false),
+            pickup_distance: distance(&mut conts, "pickup_position"),
+            insert_distance: distance(&mut conts, "insert_position"),
+            name,
+        })
+    }
+}
+
+impl Inserter {
+    /// Items moved per second, chest to chest, carrying `hand_size` items per swing. Each
+    /// half of a swing takes as long as the slower of turning half way round and changing
+    /// reach between pickup and drop.
+    pub fn items_per_second(&self, hand_size: f64) -> f64 {
+        let turn = 0.5 / self.rotation_speed;
+        let reach = (self.insert_distance - self.pickup_distance).abs() / self.extension_speed;
+        hand_size * 60f64 / (2f64 * turn.max(reach))
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pump {
+    pub name: String,
+    /// Fluid per tick.
+    pub pumping_speed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UndergroundBelt {
+    pub name: String,
+    /// Most tiles from an entrance to its exit, counting the exit.
+    pub max_distance: i64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Splitter {
+    pub name: String,
+    /// Tiles moved per tick.
+    pub speed: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ElectricPole {
+    pub name: String,
+    /// Tiles the pole powers in each direction from its centre.
+    pub supply_area_distance: f64,
+    /// Longest wire to another pole, between centres.
+    pub maximum_wire_distance: f64,
+}
+
+/// Fluid per second through `length` pipes, with pumps every `MAX_UNPUMPED_PIPES` if
+/// `pump` is given.
+pub fn pipe_throughput(length: u32, pump: Option<&Pump>) -> f64 {
+    let segment = match pump {
+        Some(_) => length.min(MAX_UNPUMPED_PIPES),
+        None => length,
+    };
+    let pipes = PIPE_THROUGHPUT
+        .iter()
+        .take_while(|(l, _)| *l <= segment.max(1))
+        .last()
+        .map_or(PIPE_THROUGHPUT[0].1, |(_, t)| *t);
+    match pump {
+        Some(pump) => pipes.min(pump.pumping_speed * 60f64),
+        None => pipes,
+    }
+}
+
+/// Belts, splitters, inserters, pumps and electric poles, read from the base mod
+/// prototypes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct LogisticsPrototypes {
+    pub belts: BTreeMap<String, TransportBelt>,
+    pub undergrounds: BTreeMap<String, UndergroundBelt>,
+    pub splitters: BTreeMap<String, Splitter>,
+    pub inserters: BTreeMap<String, Inserter>,
+    pub pumps: BTreeMap<String, Pump>,
+    pub poles: BTreeMap<String, ElectricPole>,
+}
+
+impl LogisticsPrototypes {
+    /// Fails without any transport belt, as when a prototype file is missing from
+    /// `ENTITY_FILES`, rather than failing later on the first belt looked up.
+    pub fn from_contexts(ctxs: &[LuaContext]) -> Result<Self, String> {
+        let mut logistics = LogisticsPrototypes::default();
+        for obj in prototypes_of_type(ctxs, &["transport-belt"]) {
+            let belt = TransportBelt::try_from(obj)?;
+            logistics.belts.insert(belt.name.clone(), belt);
+        }
+        for obj in prototypes_of_type(ctxs, &["inserter"]) {
+            let inserter = Inserter::try_from(obj)?;
+            logistics.inserters.insert(inserter.name.clone(), inserter);
+        }
+        for obj in prototypes_of_type(ctxs, &["pump"]) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            let name: String = conts.field("name")?;
+            let pumping_speed = conts
+                .field("pumping_speed")
+                .map_err(|e| format!("{}: {}", name, e))?;
+            logistics.pumps.insert(
+                name.clone(),
+                Pump {
+                    name,
+                    pumping_speed,
+                },
+            );
+        }
+        for obj in prototypes_of_type(ctxs, &["underground-belt"]) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            let name: String = conts.field("name")?;
+            let max_distance = conts
+                .field("max_distance")
+                .map_err(|e| format!("{}: {}", name, e))?;
+            logistics
+                .undergrounds
+                .insert(name.clone(), UndergroundBelt { name, max_distance });
+        }
+        for obj in prototypes_of_type(ctxs, &["splitter"]) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            let name: String = conts.field("name")?;
+            let speed = conts
+                .field("speed")
+                .map_err(|e| format!("{}: {}", name, e))?;
+            logistics
+                .splitters
+                .insert(name.clone(), Splitter { name, speed });
+        }
+        for obj in prototypes_of_type(ctxs, &["electric-pole"]) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            let name: String = conts.field("name")?;
+            let pole = ElectricPole {
+                supply_area_distance: conts
+                    .field("supply_area_distance")
+                    .map_err(|e| format!("{}: {}", name, e))?,
+                maximum_wire_distance: conts
+                    .field("maximum_wire_distance")
+                    .map_err(|e| format!("{}: {}", name, e))?,
+                name: name.clone(),
+            };
+            logistics.poles.insert(name, pole);
+        }
+        if logistics.belts.is_empty() {
+            return Err("No transport belts among the entity prototypes".into());
+        }
+        Ok(logistics)
+    }
+
+    /// How each flow of `plan` is transported, see `Transport`.
+    pub fn transport(
+        &self,
+        plan: &ProductionPlan,
+        config: &LogisticsConfig,
+    ) -> Result<Vec<(Flow, Transport)>, String> {
+        let belt = self
+            .belts
+            .get(&config.belt)
+            .ok_or_else(|| format!("Unknown belt {}", config.belt))?;
+        let inserter = self
+            .inserters
+            .get(&config.inserter)
+            .ok_or_else(|| format!("Unknown inserter {}", config.inserter))?;
+        let pump = match &config.pump {
+            Some(pump) => Some(
+                self.pumps
+                    .get(pump)
+                    .ok_or_else(|| format!("Unknown pump {}", pump))?,
+            ),
+            None => None,
+        };
+        let hand_size = 1f64
+            + if inserter.stack {
+                config.stack_inserter_capacity_bonus
+            } else {
+                config.inserter_stack_size_bonus
+            } as f64;
+        let inserter_rate = inserter.items_per_second(hand_size);
+        let machines: HashMap<&str, f64> = plan
+            .steps
+            .iter()
+            .map(|s| (&*s.recipe.name, s.machine_count))
+            .collect();
+        // inserters for one machine running flat out
+        let inserters = |recipe: &Option<String>, rate: f64| {
+            let count = *machines.get(recipe.as_deref()?)?;
+            if count > 0f64 {
+                Some((rate / count / inserter_rate).ceil())
+            } else {
+                None
+            }
+        };
+
+        Ok(flows(plan)
+            .into_iter()
+            .map(|flow| {
+                let transport = if flow.fluid {
+                    let throughput = pipe_throughput(config.pipe_length, pump);
+                    let pumps = match pump {
+                        Some(_) => (config.pipe_length.max(1) - 1) / MAX_UNPUMPED_PIPES,
+                        None => 0,
+                    };
+                    Transport::Pipe {
+                        pipes: (flow.rate / throughput).ceil(),
+                        pumps: pumps as f64,
+                    }
+                } else {
+                    Transport::Belt {
+                        lanes: flow.rate / belt.lane_items_per_second(),
+                        inserters_out: inserters(&flow.from, flow.rate),
+                        inserters_in: inserters(&flow.to, flow.rate),
+                    }
+                };
+                (flow, transport)
+            })
+            .collect())
+    }
+}
+
+/// Transport used for every flow of a plan.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogisticsConfig {
+    pub belt: String,
+    pub inserter: String,
+    /// Pump placed along long pipes, if any.
+    pub pump: Option<String>,
+    /// Typical length of the pipe between two machines.
+    pub pipe_length: u32,
+    /// Extra items carried by inserters from research.
+    pub inserter_stack_size_bonus: i64,
+    /// Extra items carried by stack inserters from research.
+    pub stack_inserter_capacity_bonus: i64,
+}
+
+impl Default for LogisticsConfig {
+    fn default() -> Self {
+        LogisticsConfig {
+            belt: "transport-belt".into(),
+            inserter: "fast-inserter".into(),
+            pump: None,
+            pipe_length: 10,
+            inserter_stack_size_bonus: 0,
+            stack_inserter_capacity_bonus: 0,
+        }
+    }
+}
+
+/// Part of an item's flow, from the step producing it to the step consuming it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Flow {
+    pub item: ProductId,
+    pub fluid: bool,
+    /// Producing recipe, or none for a raw input.
+    pub from: Option<String>,
+    /// Consuming recipe, or none for a goal or byproduct.
+    pub to: Option<String>,
+    /// Per second.
+    pub rate: f64,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Transport {
+    Belt {
+        /// Belt lanes filled, fractional.
+        lanes: f64,
+        /// Inserters taking the item out of each producing machine.
+        inserters_out: Option<f64>,
+        /// Inserters putting the item into each consuming machine.
+        inserters_in: Option<f64>,
+    },
+    Pipe {
+        /// Pipes laid side by side.
+        pipes: f64,
+        /// Pumps along each of them.
+        pumps: f64,
+    },
+}
+
+impl Transport {
+    /// Whether the flow needs more than one belt or pipe.
+    pub fn overloaded(&self) -> bool {
+        match self {
+            Transport::Belt { lanes, .. } => *lanes > 2f64 + 1e-9,
+            Transport::Pipe { pipes, .. } => *pipes > 1f64,
+        }
+    }
+}
+
+/// Splits the flow of every item of `plan` between its producers and consumers, with
+/// each producer supplying each consumer in proportion to what it makes.
+pub fn flows(plan: &ProductionPlan) -> Vec<Flow> {
+    let mut sources = BTreeMap::<&str, Vec<(Option<&str>, f64)>>::new();
+    let mut sinks = BTreeMap::<&str, Vec<(Option<&str>, f64)>>::new();
+    let mut fluids = HashSet::new();
+    for step in plan.steps.iter() {
+        let items = step
+            .recipe
+            .ingredients
+            .iter()
+            .chain(step.recipe.results.iter());
+        for item in items.filter(|i| i.type_ == "fluid") {
+            fluids.insert(&*item.name);
+        }
+        for (item, rate) in ProductionPlan::step_flows(step) {
+            if rate > 1e-9 {
+                sources
+                    .entry(item)
+                    .or_default()
+                    .push((Some(&step.recipe.name), rate));
+            } else if rate < -1e-9 {
+                sinks
+                    .entry(item)
+                    .or_default()
+                    .push((Some(&step.recipe.name), -rate));
+            }
+        }
+    }
+    for (item, rate) in plan.raw_inputs.iter() {
+        sources.entry(item).or_default().push((None, *rate));
+    }
+    let goals = plan.goals.iter().map(|(item, rate)| (item, rate));
+    for (item, rate) in goals.chain(plan.byproducts.iter()) {
+        sinks.entry(item).or_default().push((None, *rate));
+    }
+
+    let mut flows = Vec::new();
+    for (item, sources) in sources.iter() {
+        let total: f64 = sources.iter().map(|(_, r)| r).sum();
+        for (from, supplied) in sources.iter() {
+            for (to, consumed) in sinks.get(item).into_iter().flatten() {
+                flows.push(Flow {
+                    item: item.to_string(),
+                    fluid: fluids.contains(item),
+                    from: from.map(String::from),
+                    to: to.map(String::from),
+                    rate: supplied * consumed / total,
+                });
+            }
+        }
+    }
+    flows
+}
+
+#[test]
+fn plan_logistics() -> Result<(), String> {
+    use crate::planner::{test_recipe, Planner};
+    use crate::recipe::RecipeMap;
+
+    let lua = r#"data:extend({
+        { type = "transport-belt", name = "transport-belt", speed = 0.03125 },
+        { type = "inserter", name = "inserter", extension_speed = 0.03,
+          rotation_speed = 0.014, pickup_position = {0, -1}, insert_position = {0, 1.2} },
+        { type = "inserter", name = "stack-inserter", extension_speed = 0.07,
+          rotation_speed = 0.04, stack = true },
+        { type = "pump", name = "pump", pumping_speed = 200 },
+        { type = "underground-belt", name = "underground-belt", max_distance = 5, speed = 0.03125 },
+        { type = "splitter", name = "splitter", speed = 0.03125 },
+        { type = "electric-pole", name = "small-electric-pole", supply_area_distance = 2.5,
+          maximum_wire_distance = 7.5 }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let logistics = LogisticsPrototypes::from_contexts(&[ctx])?;
+    assert_eq!(logistics.belts["transport-belt"].items_per_second(), 15.0);
+    assert_eq!(logistics.undergrounds["underground-belt"].max_distance, 5);
+    assert_eq!(logistics.splitters["splitter"].speed, 0.03125);
+    assert_eq!(
+        logistics.poles["small-electric-pole"].maximum_wire_distance,
+        7.5
+    );
+    let inserter = &logistics.inserters["inserter"];
+    assert!((inserter.items_per_second(1.0) - 0.84).abs() < 1e-9);
+    assert!((logistics.inserters["stack-inserter"].items_per_second(12.0) - 28.8).abs() < 1e-9);
+    assert_eq!(pipe_throughput(10, None), 2000.0);
+    assert_eq!(pipe_throughput(100, None), 1200.0);
+    assert_eq!(pipe_throughput(5, Some(&logistics.pumps["pump"])), 3000.0);
+    let mut no_belts = LuaContext::new();
+    no_belts
+        .parse_all::<()>(
+            r#"data:extend({ { type = "pump", name = "pump", pumping_speed = 200 } })"#,
+        )
+        .map_err(|e| format!("{:?}", e))?;
+    assert!(LogisticsPrototypes::from_contexts(&[no_belts]).is_err());
+
+    let mut plastic = test_recipe(
+        "plastic-bar",
+        1.0,
+        &[("petroleum-gas", 20), ("coal", 1)],
+        &[("plastic-bar", 2)],
+    );
+    plastic.ingredients[0].type_ = "fluid".into();
+    let recipes = RecipeMap::new(vec![plastic]);
+    let plan = Planner::new(&recipes).solve(&[("plastic-bar".into(), 20.0)])?;
+    let config = LogisticsConfig {
+        inserter: "inserter".into(),
+        ..LogisticsConfig::default()
+    };
+    let transport = logistics.transport(&plan, &config)?;
+    assert_eq!(transport.len(), 3);
+    for (flow, transport) in transport.iter() {
+        match &*flow.item {
+            // 10 machines each taking 1 coal and making 2 plastic per second
+            "coal" => assert_eq!(
+                transport,
+                &Transport::Belt {
+                    lanes: 10.0 / 7.5,
+                    inserters_out: None,
+                    inserters_in: Some(2.0),
+                }
+            ),
+            "plastic-bar" => {
+                assert_eq!(flow.from.as_deref(), Some("plastic-bar"));
+                assert!(transport.overloaded());
+                if let Transport::Belt { inserters_out, .. } = transport {
+                    assert_eq!(*inserters_out, Some(3.0));
+                }
+            }
+            _ => assert_eq!(
+                transport,
+                &Transport::Pipe {
+                    pipes: 1.0,
+                    pumps: 0.0
+                }
+            ),
+        }
+    }
+    Ok(())
+}
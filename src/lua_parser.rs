@@ -126,7 +126,7 @@ impl<T1: TryFrom<LuaObject>, T2: TryFrom<LuaObject>> TryFrom<LuaObject> for (T1,
                         T2::try_from(b).map_err(|_| String::from("Couldn't convert second arg"))?,
                     ))
                 } else {
-                    return Err("Invalid sized array".into());
+                    Err("Invalid sized array".into())
                 }
             }
             _ => Err("Not an Array".into()),
@@ -248,7 +248,7 @@ pub fn parse_lvalue<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         map(parse_subscript, |(x, y)| {
             LValue::Subscript(Box::new(x), Box::new(y))
         }),
-        map(parse_namespaced, |x| LValue::Dotted(x)),
+        map(parse_namespaced, LValue::Dotted),
     ))(input)
 }
 
@@ -545,7 +545,7 @@ pub fn parse_stmt<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
                 LuaStmt::Assign(LValue::Dotted(vec![name]), expr)
             }),
             parse_ifthen,
-            map(parse_expr, |expr| LuaStmt::Expr(expr)),
+            map(parse_expr, LuaStmt::Expr),
         )),
     )(input)
 }
@@ -671,6 +671,12 @@ pub struct LuaContext {
     pub data_extends: Vec<LuaObject>,
 }
 
+impl Default for LuaContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl LuaContext {
     pub fn new() -> Self {
         Self {
@@ -691,6 +697,30 @@ impl LuaContext {
             }
         }
     }
+    /// Replaces calls to argument-less functions that just return a literal, such as
+    /// `productivity_module_limitation()`, with that literal.
+    pub fn inline_calls(&self, obj: LuaObject) -> LuaObject {
+        use LuaObject::*;
+        match obj {
+            Map(map) => Map(map
+                .into_iter()
+                .map(|(k, v)| (k, self.inline_calls(v)))
+                .collect()),
+            Array(array) => Array(array.into_iter().map(|x| self.inline_calls(x)).collect()),
+            Expr(x) => match &*x {
+                LuaExpr::Funcall(name, args) if args.is_empty() && name.len() == 1 => {
+                    match self.functions.get(&name[0]).map(|f| &f.body[..]) {
+                        Some([LuaStmt::Return(LuaExpr::Literal(literal))]) => {
+                            self.inline_calls(literal.clone().simplify())
+                        }
+                        _ => Expr(x),
+                    }
+                }
+                _ => Expr(x),
+            },
+            _ => obj,
+        }
+    }
     pub fn parse_toplevel<'a, E: ParseError<&'a str> + ContextError<&'a str>>(
         &mut self,
         input: &'a str,
@@ -1,36 +1,52 @@
+pub mod analysis;
+pub mod blueprint;
+pub mod config;
+pub mod costs;
+pub mod entity;
+pub mod export;
+pub mod graph;
+pub mod layout;
+pub mod logistics;
 pub mod lua_parser;
+pub mod module;
+pub mod optimizer;
+pub mod planner;
+pub mod power;
+pub mod quantize;
 pub mod recipe;
-
-use nom::{error::convert_error, Finish, Parser};
-use petgraph::Graph;
-use serde::{Deserialize, Serialize};
-use std::{
-    cmp::Ordering,
-    collections::{HashMap, HashSet, VecDeque},
-    convert::{TryFrom, TryInto},
-    error::Error,
-    fs::File,
-    io::{Read, Write},
-    iter::FromIterator,
-    path::PathBuf,
-};
-
-use crate::lua_parser::LuaExpr;
-use crate::recipe::{Ingredient, ProductId, ProductsPerSecond, Recipe, RecipeMap};
-use lua_parser::{parse_data_extend, LuaContext, LuaObject, LuaStmt};
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ModuleEffect {
-    speed: f64,
-    consumption: f64,
-    productivity: f64,
-    pollution: f64,
-}
-
-const FACTORIO_PREFIX: &'static str = "./factorio_headless/factorio/data/base/";
+pub mod report;
+pub mod resource;
+pub mod router;
+pub mod sensitivity;
+pub mod simplex;
+pub mod technology;
+
+use nom::{error::convert_error, Finish};
+use std::{convert::TryFrom, error::Error, fs::File, path::PathBuf};
+
+use crate::analysis::analyze;
+use crate::blueprint::{BlueprintString, BLUEPRINT_VERSION};
+use crate::config::{PlannerConfig, Solver};
+use crate::costs::item_costs;
+use crate::entity::{Beacon, MachineMap, ENTITY_FILES};
+use crate::export::write_plan;
+use crate::graph::{dot_file_name, ProductionGraph};
+use crate::logistics::{LogisticsPrototypes, Transport};
+use crate::module::ModuleMap;
+use crate::optimizer::OptimizedPlan;
+use crate::planner::{Planner, ProductionPlan};
+use crate::power::PowerPrototypes;
+use crate::quantize::{achievable_goals, scale_goals};
+use crate::recipe::{ProductId, Recipe, RecipeMap};
+use crate::report::write_report;
+use crate::resource::Resource;
+use crate::technology::TechTree;
+use lua_parser::LuaContext;
+
+const FACTORIO_PREFIX: &str = "./factorio_headless/factorio/data/base/";
 
 fn get_context(subpath: &str) -> Result<LuaContext, Box<dyn Error>> {
-    let data = std::fs::read_to_string(&PathBuf::from(FACTORIO_PREFIX).join(subpath))?;
+    let data = std::fs::read_to_string(PathBuf::from(FACTORIO_PREFIX).join(subpath))?;
     let mut ctx = LuaContext::new();
     ctx.parse_all::<nom::error::VerboseError<_>>(&data)
         .finish()
@@ -38,222 +54,406 @@ fn get_context(subpath: &str) -> Result<LuaContext, Box<dyn Error>> {
     Ok(ctx)
 }
 
+/// Plans `goals` with the configured optimizer or solver.
+fn make_plan(
+    planner: &Planner,
+    config: &PlannerConfig,
+    goals: &[(ProductId, f64)],
+) -> Result<(Option<OptimizedPlan>, ProductionPlan), String> {
+    match (&config.optimize, config.solver) {
+        (Some(optimizer), _) => {
+            let optimized = optimizer.optimize(planner, goals)?;
+            let plan = optimized.plan.clone();
+            Ok((Some(optimized), plan))
+        }
+        (None, Solver::LinearProgram) => Ok((None, planner.solve(goals)?)),
+        (None, Solver::Components) => Ok((None, planner.solve_by_components(goals)?)),
+    }
+}
+
 fn main() -> Result<(), Box<dyn Error>> {
+    let config = match std::env::args().nth(1) {
+        Some(path) => PlannerConfig::load(&path)?,
+        None => PlannerConfig::default(),
+    };
+
+    let entity_ctxs = ENTITY_FILES
+        .iter()
+        .map(|f| get_context(f))
+        .collect::<Result<Vec<_>, _>>()?;
+    let machine_map = MachineMap::from_contexts(&entity_ctxs)?;
+    let beacons = Beacon::from_contexts(&entity_ctxs)?;
+    let resources = Resource::from_contexts(&entity_ctxs)?;
+    let logistics = LogisticsPrototypes::from_contexts(&entity_ctxs)?;
+
     let recipe_map = {
         let ctx = get_context("prototypes/recipe.lua")?;
 
-        let mut prerecipes = Vec::new();
+        let mut raw_recipes = Vec::new();
         for objs in ctx.data_extends.into_iter() {
-            prerecipes.extend(Vec::<Recipe>::try_from(objs.simplify())?);
+            raw_recipes.extend(Vec::<Recipe>::try_from(objs.simplify())?);
         }
-
-        let raw_recipes = Vec::<Recipe>::try_from(prerecipes)?;
+        raw_recipes.extend(resources.values().map(Resource::recipe));
 
         RecipeMap::new(raw_recipes)
     };
 
-    // TODO: Parse (avi?)
-
-    // mining-drill.lua
-    let mining_speed = HashMap::<ProductId, ProductsPerSecond>::from_iter([
-        ("electric-mining-drill".into(), 0.5f64),
-        ("burner-mining-drill".into(), 0.25f64),
-        ("pumpjack".into(), 1f64),
-    ]);
-    /*let mining_speed: HashMap::<ProductId, ProductsPerSecond> = {
-        let ctx = get_context("prototypes/entity/mining-drill.lua")?;
-        panic!("{:?}", ctx);
-    };*/
-
     // item.lua
 
     let item_ctx = get_context("prototypes/item.lua")?;
 
-    let mut productivity_allowed: HashSet<String> = HashSet::new();
-    if let LuaStmt::Return(LuaExpr::Literal(obj)) =
-        &item_ctx.functions["productivity_module_limitation"].body[0]
-    {
-        productivity_allowed = HashSet::<String>::try_from(obj.clone().simplify())?;
+    let module_map = ModuleMap::from_context(&item_ctx)?;
+    let power_prototypes = PowerPrototypes::from_contexts(&entity_ctxs, &item_ctx)?;
+
+    let mut planner = Planner::new(&recipe_map);
+    planner.objective = config.objective.clone();
+    planner.raw_costs = config.raw_costs.clone();
+    planner.pinned = config.pinned.clone();
+    planner.blacklist = config.blacklist.clone();
+    planner.inputs = config.inputs.clone();
+    planner.forbidden = config.forbidden.clone();
+    planner.machines = machine_map.choose(&config.machines)?;
+    planner.modules = config.modules.clone();
+    planner.module_map = module_map;
+    planner.beacons = beacons.into_iter().collect();
+    planner.beacon_layouts = config.beacons.clone();
+    for resource in resources.keys() {
+        planner.mined.insert(resource.clone());
+        planner
+            .productivity
+            .insert(resource.clone(), config.mining_productivity);
     }
-
-    // item.lua
-    let module_bonuses = HashMap::<String, ModuleEffect>::from_iter([
-        (
-            String::from("speed-module"),
-            ModuleEffect {
-                speed: 0.2,
-                consumption: 0.5,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("speed-module-2"),
-            ModuleEffect {
-                speed: 0.3,
-                consumption: 0.6,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("speed-module-3"),
-            ModuleEffect {
-                speed: 0.5,
-                consumption: 0.7,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("efficiency-module"),
-            ModuleEffect {
-                speed: 0.0,
-                consumption: -0.3,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("efficiency-module-2"),
-            ModuleEffect {
-                speed: 0.0,
-                consumption: -0.4,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("efficiency-module-3"),
-            ModuleEffect {
-                speed: 0.0,
-                consumption: -0.5,
-                productivity: 0.0,
-                pollution: 0.0,
-            },
-        ),
-        (
-            String::from("productivity-module"),
-            ModuleEffect {
-                speed: -0.05,
-                consumption: 0.4,
-                productivity: 0.04,
-                pollution: 0.05,
-            },
-        ),
-        (
-            String::from("productivity-module-2"),
-            ModuleEffect {
-                speed: -0.1,
-                consumption: 0.6,
-                productivity: 0.06,
-                pollution: 0.07,
-            },
-        ),
-        (
-            String::from("productivity-module-3"),
-            ModuleEffect {
-                speed: -0.15,
-                consumption: 0.8,
-                productivity: 0.1,
-                pollution: 0.1,
-            },
-        ),
-    ]);
-
-    // entities.lua
-    let modules_allowed = HashMap::<String, i64>::from_iter([
-        (String::from("advanced-crafting"), 4),
-        (String::from("centrifuging"), 2),
-        (String::from("chemistry"), 3),
-        (String::from("crafting"), 4),
-        (String::from("crafting-with-fluid"), 4),
-        (String::from("oil-processing"), 3),
-        (String::from("rocket-building"), 4),
-        (String::from("smelting"), 2),
-    ]);
-
-    let goal: (ProductId, f64) = ("spidertron".into(), 1f64);
-
-    let mut graph = Graph::new();
-    let mut nodes = HashMap::new();
-    let mut requirements = HashMap::new();
-    let mut todo_requirements = VecDeque::new();
-    todo_requirements.push_back(goal.clone()); // now this is an api i can get behind
-
-    // find a recipe in the map to make this
-    while !todo_requirements.is_empty() {
-        let (product, speed) = todo_requirements.pop_front().unwrap();
-        if let Some(recipes) = recipe_map.0.get(&product) {
-            let product_node = *nodes
-                .entry(product.clone())
-                .or_insert_with(|| graph.add_node(product.clone()));
-            // Find the fastest
-            let fastest = recipes
+    let (mut optimized, mut plan) = make_plan(&planner, &config, &config.goals)?;
+    if let Some(rounding) = &config.rounding {
+        let scale = rounding.scale(&plan, &logistics.belts)?;
+        if (scale - 1f64).abs() > 1e-9 {
+            let scaled = scale_goals(&config.goals, scale);
+            let (o, p) = make_plan(&planner, &config, &scaled)?;
+            optimized = o;
+            plan = p;
+        }
+    }
+    let goals = &plan.goals[..];
+
+    let goal_names: Vec<String> = goals
+        .iter()
+        .map(|(item, rate)| format!("{} @ {}/sec", item, rate))
+        .collect();
+    println!("To make {} you need:", goal_names.join(", "));
+    for step in plan.steps.iter() {
+        match &step.machine {
+            Some(machine) if step.modules.is_empty() => println!(
+                "    {} @ {}/sec: {:.2} x {}",
+                step.recipe.name, step.rate, step.machine_count, machine
+            ),
+            Some(machine) => println!(
+                "    {} @ {}/sec: {:.2} x {} with {}, energy x{:.2}, pollution x{:.2}",
+                step.recipe.name,
+                step.rate,
+                step.machine_count,
+                machine,
+                step.modules.join(", "),
+                step.effect.energy_multiplier(),
+                step.effect.emissions_multiplier()
+            ),
+            None => println!(
+                "    {} @ {}/sec: no machine for {}",
+                step.recipe.name, step.rate, step.recipe.category
+            ),
+        }
+        if config.rounding.is_some() && step.machine.is_some() {
+            println!(
+                "        build {} ({:.0}% idle)",
+                step.built_machines(),
+                step.idle_fraction() * 1e2
+            );
+        }
+        if resources.contains_key(&step.recipe.name) {
+            for fluid in step.recipe.ingredients.iter() {
+                println!(
+                    "        consuming {} @ {}/sec",
+                    fluid.name,
+                    fluid.amount as f64 * step.rate
+                );
+            }
+        }
+        if let Some(layout) = &step.beacons {
+            println!(
+                "        {:.2} x {} with {} ({:.1} kW)",
+                step.beacon_count,
+                layout.beacon,
+                layout.modules.join(", "),
+                step.beacon_power / 1e3
+            );
+        }
+    }
+    if config.rounding.is_some() {
+        let achievable: Vec<String> = achievable_goals(&plan)
+            .iter()
+            .map(|(item, rate)| format!("{} @ {:.3}/sec", item, rate))
+            .collect();
+        println!(
+            "Whole machines: {:.0}% idle overall, up to {} at full speed",
+            plan.idle_fraction() * 1e2,
+            achievable.join(", ")
+        );
+    }
+    let choices = planner.recipe_choices(&plan);
+    if !choices.is_empty() {
+        println!("Recipe choices ({:?}):", config.objective);
+        for (item, (used, unused)) in choices.iter() {
+            println!(
+                "    {}: {} rather than {}",
+                item,
+                used.join(", "),
+                if unused.is_empty() {
+                    String::from("nothing")
+                } else {
+                    unused.join(", ")
+                }
+            );
+        }
+    }
+    if let (Some(optimizer), Some(optimized)) = (&config.optimize, &optimized) {
+        println!("Chosen loadouts ({:?} per item):", optimizer.objective);
+        for loadout in optimized.loadouts.iter() {
+            let beacons = loadout.beacons.as_ref().map_or(String::new(), |b| {
+                format!(", {} x {} with {}", b.count, b.beacon, b.modules.join(", "))
+            });
+            println!(
+                "    {}: [{}]{}: {:.3} instead of {:.3}",
+                loadout.recipe,
+                loadout.modules.join(", "),
+                beacons,
+                loadout.cost,
+                loadout.baseline_cost
+            );
+        }
+        println!(
+            "Total {:?}: {:.2} instead of {:.2} without modules",
+            optimizer.objective, optimized.total, optimized.baseline_total
+        );
+    }
+    println!(
+        "Power: {:.2} MW electric, {:.2} MW of burnt fuel",
+        plan.electric_power() / 1e6,
+        plan.burner_power() / 1e6
+    );
+    if plan.burner_power() > 0f64 {
+        if let Some(fuel_value) = power_prototypes.fuel_values.get(&config.fuel) {
+            println!(
+                "    {} @ {}/sec for burners",
+                config.fuel,
+                plan.burner_power() / fuel_value
+            );
+        }
+    }
+    if let Some(power_plant) = config.power_plant {
+        let size = power_prototypes.size(power_plant, plan.electric_power(), &config.fuel)?;
+        println!("{:?} power plant:", power_plant);
+        for (entity, count) in size.entities.iter() {
+            println!("    {:.2} x {}", count, entity);
+        }
+        if let Some((fuel, rate)) = size.fuel {
+            println!("    {} @ {}/sec", fuel, rate);
+        }
+    }
+    println!("Pollution: {:.1}/min", plan.pollution());
+    let mut polluters: Vec<_> = plan.steps.iter().filter(|s| s.pollution > 0f64).collect();
+    polluters.sort_by(|a, b| b.pollution.partial_cmp(&a.pollution).unwrap());
+    for step in polluters {
+        println!("    {}: {:.1}/min", step.recipe.name, step.pollution);
+    }
+    if goals.len() > 1 {
+        println!("Shared by goals:");
+        for (item, rates) in plan.attribution()?.iter() {
+            let shared: Vec<String> = goals
                 .iter()
-                .min_by(|&a, &b| a.speed.partial_cmp(&b.speed).unwrap_or(Ordering::Equal))
-                .expect("Recipes should have entries");
+                .zip(rates.iter())
+                .filter(|(_, &rate)| rate > 1e-9)
+                .map(|((goal, _), rate)| format!("{:.3}/sec for {}", rate, goal))
+                .collect();
+            if shared.len() > 1 {
+                println!("    {}: {}", item, shared.join(", "));
+            }
+        }
+    }
+    println!("Raw inputs:");
+    for (product, speed) in plan.raw_inputs.iter() {
+        println!("    {} @ {}/sec", product, speed);
+    }
+    if !plan.byproducts.is_empty() {
+        println!("Byproducts:");
+        for (product, speed) in plan.byproducts.iter() {
+            println!("    {} @ {}/sec", product, speed);
+        }
+    }
 
-            let output_amount = fastest
-                .results
-                .iter()
-                .filter_map(|res| {
-                    if res.name == product {
-                        Some(res.amount)
-                    } else {
-                        None
-                    }
-                })
-                .next()
-                .expect("Recipe should have product as a result");
+    if let Some(table) = &config.cost_table {
+        let (mut costs, failures) = item_costs(&planner);
+        table.sort(&mut costs);
+        println!("Cost of one of each item per second:");
+        table.write(&mut std::io::stdout(), &costs)?;
+        for (item, e) in failures.iter() {
+            println!("    {} left out: {}", item, e);
+        }
+    }
+    if let Some(limits) = &config.limits {
+        let bottlenecks = limits.bottlenecks(&planner, goals)?;
+        println!("Within the limits: {:.3} x the goals", bottlenecks.scale);
+        for (item, gain) in bottlenecks.supply.iter() {
+            println!("    +{:.4} x per extra {}/sec", gain, item);
+        }
+        for (machine, gain) in bottlenecks.machines.iter() {
+            println!("    +{:.4} x per extra {}", gain, machine);
+        }
+        for change in config.what_if.iter() {
+            let what_if = limits.what_if(&planner, goals, change)?;
+            println!(
+                "    with {:?}: {:.3} x ({:+.3})",
+                change,
+                what_if.after,
+                what_if.delta()
+            );
+        }
+    }
 
-            for ingredient in &fastest.ingredients {
+    let transport = match &config.logistics {
+        Some(logistics_config) => Some(logistics.transport(&plan, logistics_config)?),
+        None => None,
+    };
+    if let (Some(logistics_config), Some(transport)) = (&config.logistics, &transport) {
+        println!("Logistics:");
+        for (flow, transport) in transport.iter() {
+            let needs = match transport {
+                Transport::Belt {
+                    lanes,
+                    inserters_out,
+                    inserters_in,
+                } => {
+                    let mut needs = format!("{:.2} lanes of {}", lanes, logistics_config.belt);
+                    if let Some(count) = inserters_out {
+                        needs += &format!(", {} out of each machine", count);
+                    }
+                    if let Some(count) = inserters_in {
+                        needs += &format!(", {} into each machine", count);
+                    }
+                    needs
+                }
+                Transport::Pipe { pipes, pumps } => format!("{} pipes with {} pumps", pipes, pumps),
+            };
+            println!(
+                "    {} @ {:.3}/sec from {} to {}: {}{}",
+                flow.item,
+                flow.rate,
+                flow.from.as_deref().unwrap_or("inputs"),
+                flow.to.as_deref().unwrap_or("outputs"),
+                needs,
+                if transport.overloaded() {
+                    " (more than one belt or pipe)"
+                } else {
+                    ""
+                }
+            );
+        }
+    }
+    if let Some(path) = &config.blueprint {
+        let string = BlueprintString::decode(&std::fs::read_to_string(path)?)?;
+        for blueprint in string.blueprints() {
+            let analysis = analyze(blueprint, &planner, &machine_map, &logistics);
+            println!(
+                "Blueprint {}: {} machines",
+                blueprint.label.as_deref().unwrap_or(path),
+                analysis.machines.len()
+            );
+            for machine in analysis.machines.iter() {
                 println!(
-                    "Using {} to make {} x {} from {:?}",
-                    ingredient.name, product, output_amount, fastest
+                    "    {} #{}: {} @ {:.3}/sec [{}] with {} beacons",
+                    machine.machine,
+                    machine.entity_number,
+                    machine.recipe,
+                    machine.rate,
+                    machine.modules.join(", "),
+                    machine.beacons.len()
                 );
-                let ingredient_node = *nodes
-                    .entry(ingredient.name.clone())
-                    .or_insert_with(|| graph.add_node(ingredient.name.clone()));
-                graph.update_edge(ingredient_node, product_node, ());
-                let mut modded_rate = speed * (ingredient.amount as f64) / (output_amount as f64);
-                if productivity_allowed.contains(&fastest.name) {
-                    let modules = vec![
-                        String::from("productivity-module-3"); // why settle for anything less
-                        *modules_allowed.get(&fastest.category).expect("Unknown category") as usize
-                    ];
-
-                    let module_effect: f64 = modules
-                        .into_iter()
-                        .map(|m| {
-                            module_bonuses
-                                .get(&*m)
-                                .expect("Unknown module")
-                                .productivity
-                        })
-                        .sum();
-                    // modded_rate /= 1f64 + module_effect;
-                }
-                todo_requirements.push_back((ingredient.name.clone(), modded_rate));
             }
-        } else {
-            if let Some(req) = requirements.get_mut(&product) {
-                *req += speed;
-            } else {
-                requirements.insert(product, speed);
+            for (item, rate) in analysis.production.iter() {
+                println!("    makes {} @ {:.3}/sec", item, rate);
+            }
+            for (item, rate) in analysis.consumption.iter() {
+                println!("    takes {} @ {:.3}/sec", item, rate);
+            }
+            for problem in analysis.problems.iter() {
+                println!("    problem: {}", problem);
+            }
+            match analysis.plan(&planner) {
+                Ok(planned) => println!(
+                    "    the planner makes the same with {:.2} machines",
+                    planned.steps.iter().map(|s| s.machine_count).sum::<f64>()
+                ),
+                Err(e) => println!("    the planner cannot make the same: {}", e),
             }
         }
     }
+    if let Some(layout_config) = &config.layout {
+        let layout = layout_config.strategy.layout_strategy().place(
+            &plan,
+            &planner,
+            &logistics,
+            layout_config,
+        )?;
+        println!("Layout: {} entities", layout.entities.len());
+        for port in layout.ports.iter() {
+            println!(
+                "    {} {} at {:?}",
+                port.item,
+                if port.input { "in" } else { "out" },
+                port.tile
+            );
+        }
+        for note in layout.notes.iter() {
+            println!("    note: {}", note);
+        }
+        for problem in layout.check(&logistics) {
+            println!("    problem: {}", problem);
+        }
+        let label = goals
+            .iter()
+            .map(|(item, rate)| format!("{} @ {:.3}/sec", item, rate))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let string =
+            BlueprintString::Blueprint(layout.to_blueprint(Some(label), BLUEPRINT_VERSION));
+        std::fs::write(&layout_config.path, string.encode()?)?;
+    }
 
-    println!("To make {} @ {}/sec you need:", goal.0, goal.1);
-    for (product, speed) in requirements {
-        println!("    {} @ {}/sec", product, speed);
+    {
+        let mut f = File::create(dot_file_name(goals))?;
+        ProductionGraph::new(&plan).write_dot(&mut f, transport.as_deref())?;
+    }
+    if let Some(path) = &config.report {
+        let mut f = File::create(path)?;
+        write_report(&mut f, &plan)?;
+    }
+    for export in config.exports.iter() {
+        let mut f = File::create(&export.path)?;
+        write_plan(&mut f, &plan, export.format)?;
     }
 
     {
-        use petgraph::dot::{Config, Dot};
-        let mut f = File::create("spidertron.dot")?;
-        write!(f, "{:?}", Dot::with_config(&graph, &[Config::EdgeNoLabel]))?;
+        let (tech_tree, skipped) =
+            TechTree::from_context(get_context("prototypes/technology.lua")?);
+        for e in skipped {
+            println!("Skipping technology group: {}", e);
+        }
+        // highlight the path to whichever goal is unlocked last
+        let target = goals
+            .iter()
+            .filter_map(|(item, _)| tech_tree.unlocking(item))
+            .max_by_key(|t| tech_tree.ancestors(&t.name).len())
+            .map(|t| t.name.clone());
+        let mut f = File::create("technology.dot")?;
+        tech_tree.write_dot(&mut f, target.as_deref())?;
     }
 
     // println!("{}", ron::ser::to_string_pretty(&recipe_map, PrettyConfig::default())?);
@@ -263,6 +463,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
 #[test]
 fn parse_item() -> Result<(), Box<dyn Error>> {
+    use std::io::Read;
     let mut data = File::open("./factorio_headless/factorio/data/base/prototypes/item.lua")?;
     let mut string_data = String::new();
     data.read_to_string(&mut string_data)?;
@@ -281,75 +482,9 @@ fn parse_item() -> Result<(), Box<dyn Error>> {
     Ok(())
 }
 
-#[derive(Debug)]
-struct Technology {
-    name: String,
-    type_: String,
-    effects: Vec<LuaObject>,
-    prerequisites: Vec<String>,
-    ingredient_count: LuaObject,
-    ingredients: Vec<Ingredient>,
-    ingredient_time: f64,
-}
-
-impl TryFrom<LuaObject> for Technology {
-    type Error = String;
-
-    fn try_from(value: LuaObject) -> Result<Self, Self::Error> {
-        let mut map = HashMap::<String, LuaObject>::try_from(value)?;
-        let name = map
-            .remove_entry("name")
-            .ok_or("No key 'name'".into())
-            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
-        let type_ = map
-            .remove_entry("type")
-            .ok_or("No key 'type'".into())
-            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
-        let effects = map.remove_entry("effects").map_or_else(
-            || Ok(vec![]),
-            |(_, l)| <_ as TryFrom<LuaObject>>::try_from(l),
-        )?;
-        let prerequisites = map.remove_entry("prerequisites").map_or_else(
-            || Ok(vec![]),
-            |(_, l)| <_ as TryFrom<LuaObject>>::try_from(l),
-        )?;
-
-        let mut unit = map
-            .remove_entry("unit")
-            .ok_or("No key 'unit'".into())
-            .and_then(|(_, l)| HashMap::<String, LuaObject>::try_from(l))?;
-        let ingredient_count = unit.remove_entry("count").map_or_else(
-            || {
-                unit.remove_entry("count_formula")
-                    .ok_or(String::from("No key 'count' or 'count_formula'"))
-                    .map(|(_, l)| l)
-            },
-            |(_, l)| Ok(l),
-        )?;
-        let ingredients = unit
-            .remove_entry("ingredients")
-            .ok_or("No key 'ingredients'".into())
-            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
-        let ingredient_time = unit
-            .remove_entry("time")
-            .ok_or("No key 'time'".into())
-            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
-
-        Ok(Technology {
-            name,
-            type_,
-            effects,
-            prerequisites,
-            ingredient_count,
-            ingredients,
-            ingredient_time,
-        })
-    }
-}
-
 #[test]
 fn parse_technology() -> Result<(), Box<dyn Error>> {
-    let mut string_data = std::fs::read_to_string(
+    let string_data = std::fs::read_to_string(
         "./factorio_headless/factorio/data/base/prototypes/technology.lua",
     )?;
     let mut ctx = LuaContext::new();
@@ -361,49 +496,22 @@ fn parse_technology() -> Result<(), Box<dyn Error>> {
         panic!("{}", convert_error(&*string_data, e));
     }
 
-    let mut all_techs = HashMap::new();
-    for group in ctx.data_extends {
-        let techs = Vec::<Technology>::try_from(group.simplify());
-        if let Ok(techs) = techs {
-            for tech in techs {
-                println!("{:?}", tech);
-                all_techs.insert(tech.name.clone(), tech);
-            }
-        } else {
-            println!("{:?}", techs);
-        }
-    }
+    let (tree, _) = TechTree::from_context(ctx);
+    let mut f = File::create("technology.dot")?;
+    tree.write_dot(&mut f, None)?;
+    Ok(())
+}
 
-    let mut graph = Graph::new();
-    let mut nodes = HashMap::new();
-    for (_, tech) in all_techs.iter() {
-        let tech_node = *nodes
-            .entry(tech.name.clone())
-            .or_insert_with(|| graph.add_node(tech.name.clone()));
-        for prereq in tech.prerequisites.iter() {
-            let prereq_node = *nodes
-                .entry(prereq.clone())
-                .or_insert_with(|| graph.add_node(prereq.clone()));
-            graph.update_edge(prereq_node, tech_node, ());
-        }
-    }
-    {
-        use petgraph::dot::{Config, Dot};
-        let mut f = File::create("technology.dot")?;
-        writeln!(f, "digraph {{").unwrap();
-        writeln!(f, "rankdir = \"LR\"").unwrap();
-        writeln!(
-            f,
-            "{:#?}",
-            Dot::with_attr_getters(
-                &graph,
-                &[Config::EdgeNoLabel, Config::GraphContentOnly],
-                &|_, _| "".to_owned(),
-                &|_, _| { "constraint=false".to_owned() }
-            )
-        )
-        .unwrap();
-        writeln!(f, "}}").unwrap();
-    }
+#[test]
+fn parse_logistics() -> Result<(), Box<dyn Error>> {
+    let entity_ctxs = ENTITY_FILES
+        .iter()
+        .map(|f| get_context(f))
+        .collect::<Result<Vec<_>, _>>()?;
+    let logistics = LogisticsPrototypes::from_contexts(&entity_ctxs)?;
+    assert!(logistics.belts.contains_key("transport-belt"));
+    assert!(logistics.undergrounds.contains_key("underground-belt"));
+    assert!(logistics.splitters.contains_key("splitter"));
+    assert!(logistics.poles.contains_key("small-electric-pole"));
     Ok(())
 }
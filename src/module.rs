@@ -0,0 +1,247 @@
+use crate::entity::prototypes_of_type;
+use crate::lua_parser::{LuaContext, LuaObject};
+use crate::recipe::ConversionExt;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::convert::{TryFrom, TryInto};
+use std::ops::{Add, Mul};
+
+/// Lowest value the game allows the summed speed, consumption and pollution bonuses of a
+/// machine to reach, i.e. no machine runs below 20% speed or energy use.
+const MINIMUM_BONUS: f64 = -0.8;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct ModuleEffect {
+    pub speed: f64,
+    pub consumption: f64,
+    pub productivity: f64,
+    pub pollution: f64,
+}
+
+impl Add for ModuleEffect {
+    type Output = ModuleEffect;
+
+    fn add(self, other: ModuleEffect) -> ModuleEffect {
+        ModuleEffect {
+            speed: self.speed + other.speed,
+            consumption: self.consumption + other.consumption,
+            productivity: self.productivity + other.productivity,
+            pollution: self.pollution + other.pollution,
+        }
+    }
+}
+
+impl Mul<f64> for ModuleEffect {
+    type Output = ModuleEffect;
+
+    fn mul(self, factor: f64) -> ModuleEffect {
+        ModuleEffect {
+            speed: self.speed * factor,
+            consumption: self.consumption * factor,
+            productivity: self.productivity * factor,
+            pollution: self.pollution * factor,
+        }
+    }
+}
+
+impl ModuleEffect {
+    /// The effect as the game applies it once all bonuses are summed.
+    pub fn clamped(self) -> Self {
+        ModuleEffect {
+            speed: self.speed.max(MINIMUM_BONUS),
+            consumption: self.consumption.max(MINIMUM_BONUS),
+            productivity: self.productivity.max(0f64),
+            pollution: self.pollution.max(MINIMUM_BONUS),
+        }
+    }
+
+    pub fn speed_multiplier(&self) -> f64 {
+        1f64 + self.speed
+    }
+
+    pub fn energy_multiplier(&self) -> f64 {
+        1f64 + self.consumption
+    }
+
+    /// Pollution scales with both the energy drawn and the pollution bonus.
+    pub fn emissions_multiplier(&self) -> f64 {
+        (1f64 + self.consumption) * (1f64 + self.pollution)
+    }
+
+    /// Names of the effects this changes, as used by `allowed_effects` in prototypes.
+    pub fn effect_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.speed != 0f64 {
+            names.push("speed");
+        }
+        if self.consumption != 0f64 {
+            names.push("consumption");
+        }
+        if self.productivity != 0f64 {
+            names.push("productivity");
+        }
+        if self.pollution != 0f64 {
+            names.push("pollution");
+        }
+        names
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Module {
+    pub name: String,
+    pub category: String,
+    pub tier: i64,
+    pub effect: ModuleEffect,
+    /// Recipes the module may be used for; any recipe if absent.
+    pub limitation: Option<HashSet<String>>,
+}
+
+impl TryFrom<LuaObject> for Module {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        let bonuses: HashMap<String, HashMap<String, f64>> = conts
+            .field("effect")
+            .map_err(|e| format!("{}: {}", name, e))?;
+        let mut effect = ModuleEffect::default();
+        for (kind, bonus) in bonuses {
+            let bonus = bonus.get("bonus").copied().unwrap_or(0f64);
+            match &*kind {
+                "speed" => effect.speed = bonus,
+                "consumption" => effect.consumption = bonus,
+                "productivity" => effect.productivity = bonus,
+                "pollution" => effect.pollution = bonus,
+                _ => return Err(format!("{}: unknown effect {}", name, kind)),
+            }
+        }
+        Ok(Module {
+            category: conts
+                .field("category")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            tier: conts.field("tier").unwrap_or(1),
+            effect,
+            limitation: conts.field("limitation").ok(),
+            name,
+        })
+    }
+}
+
+impl Module {
+    pub fn allows(&self, recipe: &str) -> bool {
+        self.limitation
+            .as_ref()
+            .is_none_or(|recipes| recipes.contains(recipe))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModuleMap(pub BTreeMap<String, Module>);
+
+impl ModuleMap {
+    pub fn new(modules: Vec<Module>) -> Self {
+        ModuleMap(modules.into_iter().map(|m| (m.name.clone(), m)).collect())
+    }
+
+    /// Reads the `module` items of item.lua, resolving `limitation` function calls.
+    pub fn from_context(ctx: &LuaContext) -> Result<Self, String> {
+        let modules = prototypes_of_type(std::slice::from_ref(ctx), &["module"])
+            .into_iter()
+            .map(|obj| Module::try_from(ctx.inline_calls(obj)))
+            .collect::<Result<_, _>>()?;
+        Ok(ModuleMap::new(modules))
+    }
+}
+
+/// Beacons surrounding each machine of a production step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BeaconLayout {
+    pub beacon: String,
+    /// Beacons in range of each machine.
+    pub count: i64,
+    /// Modules inserted in each beacon.
+    pub modules: Vec<String>,
+    /// Machines in range of each beacon, so that a row of machines sharing beacons is not
+    /// counted as needing `count` beacons apiece.
+    #[serde(default = "one")]
+    pub shared_by: f64,
+}
+
+fn one() -> f64 {
+    1f64
+}
+
+impl BeaconLayout {
+    /// Beacons needed for `machine_count` machines.
+    pub fn beacons_for(&self, machine_count: f64) -> f64 {
+        machine_count * self.count as f64 / self.shared_by
+    }
+}
+
+#[test]
+fn module_effect_clamps() {
+    let efficiency_3 = ModuleEffect {
+        consumption: -0.5,
+        ..ModuleEffect::default()
+    };
+    let productivity_3 = ModuleEffect {
+        speed: -0.15,
+        consumption: 0.8,
+        productivity: 0.1,
+        pollution: 0.1,
+    };
+
+    let effect = (efficiency_3 + efficiency_3 + efficiency_3).clamped();
+    assert_eq!(effect.consumption, -0.8);
+    assert!((effect.energy_multiplier() - 0.2).abs() < 1e-9);
+
+    let effect = (0..4).fold(ModuleEffect::default(), |e, _| e + productivity_3);
+    assert!((effect.speed_multiplier() - 0.4).abs() < 1e-9);
+    assert!((effect.energy_multiplier() - 4.2).abs() < 1e-9);
+    assert!((effect.emissions_multiplier() - 4.2 * 1.4).abs() < 1e-9);
+    assert_eq!(
+        productivity_3.effect_names(),
+        vec!["speed", "consumption", "productivity", "pollution"]
+    );
+}
+
+#[test]
+fn parse_modules() -> Result<(), String> {
+    let lua = r#"function productivity_module_limitation()
+      return
+      {
+        "sulfuric-acid",
+        "plastic-bar"
+      }
+    end
+
+    data:extend({
+      { type = "module", name = "speed-module", category = "speed", tier = 1,
+        effect = { speed = {bonus = 0.2}, consumption = {bonus = 0.5}} },
+      { type = "module", name = "productivity-module", category = "productivity", tier = 1,
+        effect = { productivity = {bonus = 0.04}, consumption = {bonus = 0.4},
+                   pollution = {bonus = 0.05}, speed = {bonus = -0.05}},
+        limitation = productivity_module_limitation(),
+        limitation_message_key = "production-module-usable-only-on-intermediates" },
+      { type = "item", name = "beacon", stack_size = 10 }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let modules = ModuleMap::from_context(&ctx)?;
+    assert_eq!(modules.0.len(), 2);
+
+    let speed = &modules.0["speed-module"];
+    assert_eq!(speed.category, "speed");
+    assert_eq!(speed.effect.speed, 0.2);
+    assert!(speed.allows("iron-gear-wheel"));
+
+    let productivity = &modules.0["productivity-module"];
+    assert_eq!(productivity.effect.speed, -0.05);
+    assert_eq!(productivity.effect.productivity, 0.04);
+    assert!(productivity.allows("plastic-bar"));
+    assert!(!productivity.allows("iron-chest"));
+    Ok(())
+}
@@ -0,0 +1,317 @@
+//! Picks module and beacon loadouts for the steps of a production plan.
+
+use crate::module::BeaconLayout;
+use crate::planner::{MachineSetup, Planner, ProductionPlan};
+use crate::recipe::{ProductId, Recipe};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// Costs closer than this are considered equal, and the loadout with fewer modules wins.
+const COST_EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LoadoutObjective {
+    /// Crafting machines; beacons are not counted.
+    MachineCount,
+    /// Electrical power of machines and beacons, in watts.
+    Power,
+    /// Pollution per minute of the machines.
+    Pollution,
+    /// Tiles covered by machines and beacons.
+    Footprint,
+}
+
+/// Chooses per-recipe modules and beacons for `Planner::solve`, trying every combination
+/// of modules that fits each machine and every beacon count in `beacon_counts`.
+///
+/// Each recipe is tuned on its own, minimizing the objective per item it produces, and the
+/// plan is then solved again, since productivity lowers the rates needed upstream.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ModuleOptimizer {
+    pub objective: LoadoutObjective,
+    /// Beacon to consider placing around machines; none if absent.
+    pub beacon: Option<String>,
+    /// Beacons in range of each machine to consider, besides none at all.
+    pub beacon_counts: Vec<i64>,
+    /// See `BeaconLayout::shared_by`.
+    pub shared_by: f64,
+}
+
+impl Default for ModuleOptimizer {
+    fn default() -> Self {
+        ModuleOptimizer {
+            objective: LoadoutObjective::MachineCount,
+            beacon: None,
+            beacon_counts: vec![4, 8, 12],
+            shared_by: 1f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Loadout {
+    pub recipe: String,
+    pub modules: Vec<String>,
+    pub beacons: Option<BeaconLayout>,
+    /// Objective per item produced per second, with this loadout and without any modules.
+    pub cost: f64,
+    pub baseline_cost: f64,
+}
+
+#[derive(Debug, Clone)]
+pub struct OptimizedPlan {
+    pub plan: ProductionPlan,
+    /// Loadout of each step of `plan`, in the same order.
+    pub loadouts: Vec<Loadout>,
+    /// Objective of the whole plan, and of the plan solved without modules or beacons.
+    pub total: f64,
+    pub baseline_total: f64,
+}
+
+/// Every multiset of at most `max` of `items`, each in the order of `items`.
+fn multisets(items: &[String], max: usize) -> Vec<Vec<String>> {
+    fn extend(
+        items: &[String],
+        start: usize,
+        remaining: usize,
+        current: &mut Vec<String>,
+        out: &mut Vec<Vec<String>>,
+    ) {
+        out.push(current.clone());
+        if remaining == 0 {
+            return;
+        }
+        for i in start..items.len() {
+            current.push(items[i].clone());
+            extend(items, i, remaining - 1, current, out);
+            current.pop();
+        }
+    }
+    let mut out = Vec::new();
+    extend(items, 0, max, &mut Vec::new(), &mut out);
+    out
+}
+
+impl ModuleOptimizer {
+    /// Objective per craft per second of `recipe` crafted with `setup`.
+    pub fn cost(&self, recipe: &Recipe, setup: &MachineSetup) -> f64 {
+        let machines = setup.machines_per_rate(recipe);
+        let beacons = setup
+            .beacons
+            .as_ref()
+            .map(|(beacon, layout)| (beacon, layout.beacons_for(machines)));
+        match self.objective {
+            LoadoutObjective::MachineCount => machines,
+            LoadoutObjective::Power => {
+                let machine_power = setup
+                    .machine
+                    .as_ref()
+                    .filter(|m| m.energy_source.is_electric())
+                    .map_or(0f64, |m| m.energy_usage);
+                machines * machine_power * setup.effect.energy_multiplier()
+                    + beacons.map_or(0f64, |(beacon, count)| count * beacon.energy_usage)
+            }
+            LoadoutObjective::Pollution => setup.pollution_per_rate(recipe),
+            LoadoutObjective::Footprint => {
+                let (w, h) = setup.machine.as_ref().map_or((1, 1), |m| m.size);
+                machines * f64::from(w * h)
+                    + beacons.map_or(0f64, |(beacon, count)| {
+                        count * f64::from(beacon.size.0 * beacon.size.1)
+                    })
+            }
+        }
+    }
+
+    /// Objective of a plan solved by `planner`.
+    pub fn plan_cost(&self, planner: &Planner, plan: &ProductionPlan) -> Result<f64, String> {
+        let mut total = 0f64;
+        for step in plan.steps.iter() {
+            total += self.cost(&step.recipe, &planner.setup(&step.recipe)?) * step.rate;
+        }
+        Ok(total)
+    }
+
+    /// Beacon layouts to try around machines crafting `recipe`, each filled with a single
+    /// kind of module.
+    fn beacon_layouts(&self, planner: &Planner, recipe: &Recipe) -> Vec<BeaconLayout> {
+        let beacon = match self.beacon.as_ref().and_then(|b| planner.beacons.get(b)) {
+            Some(beacon) => beacon,
+            None => return Vec::new(),
+        };
+        let mut layouts = Vec::new();
+        for module in planner.module_map.0.keys() {
+            for &count in self.beacon_counts.iter().filter(|&&c| c > 0) {
+                let layout = BeaconLayout {
+                    beacon: beacon.name.clone(),
+                    count,
+                    modules: vec![module.clone(); beacon.module_slots as usize],
+                    shared_by: self.shared_by,
+                };
+                if planner
+                    .setup_with(recipe, Vec::new(), Some(&layout))
+                    .is_ok()
+                {
+                    layouts.push(layout);
+                }
+            }
+        }
+        layouts
+    }
+
+    /// The cheapest loadout for `recipe` per item produced.
+    pub fn best_loadout(&self, planner: &Planner, recipe: &Recipe) -> Result<Loadout, String> {
+        let per_item =
+            |setup: &MachineSetup| self.cost(recipe, setup) / (1f64 + setup.effect.productivity);
+        let baseline_cost = per_item(&planner.setup_with(recipe, Vec::new(), None)?);
+
+        let slots = planner
+            .machines
+            .get(&recipe.category)
+            .map_or(0, |m| m.module_slots) as usize;
+        let usable: Vec<String> = planner
+            .module_map
+            .0
+            .keys()
+            .filter(|m| slots > 0 && planner.setup_with(recipe, vec![(*m).clone()], None).is_ok())
+            .cloned()
+            .collect();
+        let layouts = self.beacon_layouts(planner, recipe);
+
+        let mut best = Loadout {
+            recipe: recipe.name.clone(),
+            modules: Vec::new(),
+            beacons: None,
+            cost: baseline_cost,
+            baseline_cost,
+        };
+        let mut best_size = 0;
+        for modules in multisets(&usable, slots) {
+            for layout in std::iter::once(None).chain(layouts.iter().map(Some)) {
+                let setup = match planner.setup_with(recipe, modules.clone(), layout) {
+                    Ok(setup) => setup,
+                    Err(_) => continue,
+                };
+                let cost = per_item(&setup);
+                let size = modules.len() + layout.map_or(0, |l| l.modules.len() * l.count as usize);
+                if cost < best.cost - COST_EPSILON
+                    || (cost <= best.cost + COST_EPSILON && size < best_size)
+                {
+                    best.modules = modules.clone();
+                    best.beacons = layout.cloned();
+                    best.cost = cost;
+                    best_size = size;
+                }
+            }
+        }
+        Ok(best)
+    }
+
+    /// Solves `goals` with the best loadout for every step, replacing any modules and
+    /// beacons configured in `planner`.
+    pub fn optimize(
+        &self,
+        planner: &Planner,
+        goals: &[(ProductId, f64)],
+    ) -> Result<OptimizedPlan, String> {
+        let mut baseline = planner.clone();
+        baseline.modules.clear();
+        baseline.beacon_layouts.clear();
+        let baseline_plan = baseline.solve(goals)?;
+
+        // Productivity changes the rates of the other steps, and can make recipes worth
+        // using that were not before, so repeat until every step has a loadout.
+        let mut tuned = baseline.clone();
+        let mut loadouts = BTreeMap::new();
+        let mut plan = baseline_plan.clone();
+        loop {
+            let mut changed = false;
+            for step in plan.steps.iter() {
+                if loadouts.contains_key(&step.recipe.name) {
+                    continue;
+                }
+                let loadout = self.best_loadout(&baseline, &step.recipe)?;
+                tuned
+                    .modules
+                    .insert(step.recipe.name.clone(), loadout.modules.clone());
+                if let Some(layout) = &loadout.beacons {
+                    tuned
+                        .beacon_layouts
+                        .insert(step.recipe.name.clone(), layout.clone());
+                }
+                loadouts.insert(step.recipe.name.clone(), loadout);
+                changed = true;
+            }
+            if !changed {
+                break;
+            }
+            plan = tuned.solve(goals)?;
+        }
+
+        Ok(OptimizedPlan {
+            loadouts: plan
+                .steps
+                .iter()
+                .map(|s| loadouts[&s.recipe.name].clone())
+                .collect(),
+            total: self.plan_cost(&tuned, &plan)?,
+            baseline_total: self.plan_cost(&baseline, &baseline_plan)?,
+            plan,
+        })
+    }
+}
+
+#[test]
+fn optimize_loadouts() -> Result<(), String> {
+    use crate::entity::Beacon;
+    use crate::planner::{oil_recipes, test_modules, test_refinery};
+
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+    planner.machines.insert("crafting".into(), test_refinery());
+    planner.module_map = test_modules();
+    planner.beacons.insert(
+        "beacon".into(),
+        Beacon {
+            name: "beacon".into(),
+            distribution_effectivity: 0.5,
+            module_slots: 2,
+            allowed_effects: Some(vec!["speed".into(), "consumption".into()]),
+            energy_usage: 480e3,
+            supply_area_distance: 3.0,
+            size: (3, 3),
+        },
+    );
+    let goals = [("petroleum-gas".into(), 97.5)];
+
+    let mut optimizer = ModuleOptimizer::default();
+    let optimized = optimizer.optimize(&planner, &goals)?;
+    for loadout in optimized.loadouts.iter() {
+        assert_eq!(
+            loadout.modules,
+            vec!["speed-module"; 3],
+            "{}",
+            loadout.recipe
+        );
+        assert!(loadout.beacons.is_none());
+    }
+    assert!(optimized.total < optimized.baseline_total / 1.5);
+
+    // Every module raises energy use, so the least power is drawn without any.
+    optimizer.objective = LoadoutObjective::Power;
+    let optimized = optimizer.optimize(&planner, &goals)?;
+    assert!(optimized.loadouts.iter().all(|l| l.modules.is_empty()));
+    assert!((optimized.total - optimized.baseline_total).abs() < 1e-6);
+
+    // Beacons shared by enough machines pay for their own tiles.
+    optimizer.objective = LoadoutObjective::Footprint;
+    optimizer.beacon = Some("beacon".into());
+    optimizer.shared_by = 8.0;
+    let optimized = optimizer.optimize(&planner, &goals)?;
+    let layout = optimized.loadouts[0].beacons.as_ref().unwrap();
+    assert_eq!(layout.count, 12);
+    assert_eq!(layout.modules, vec!["speed-module"; 2]);
+    assert!(optimized.total < optimized.baseline_total);
+    Ok(())
+}
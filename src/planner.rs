@@ -0,0 +1,1525 @@
+use crate::entity::{Beacon, CraftingMachine};
+use crate::module::{BeaconLayout, ModuleEffect, ModuleMap};
+use crate::recipe::{Ingredient, ProductId, Recipe, RecipeMap};
+use crate::simplex::{solve_linear_system, LinearProgram, Relation};
+use petgraph::{algo::tarjan_scc, Graph};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque};
+
+/// Rates below this are treated as zero when reading back a solution.
+const RATE_EPSILON: f64 = 1e-9;
+/// Cost given to otherwise free variables, so that among equally good plans the one
+/// without pointless crafting or overproduction wins.
+pub(crate) const TIEBREAK: f64 = 1e-6;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Objective {
+    /// Minimize the raw resources consumed, weighted by `Planner::raw_costs`.
+    RawResources,
+    /// Minimize the crafting time of all recipes at crafting speed 1, whatever the machine.
+    CraftingTime,
+    /// Minimize the number of machines, using the machine chosen for each category.
+    MachineCount,
+    /// Minimize the power drawn by machines and beacons, in megawatts.
+    Power,
+    /// Minimize a weighted sum of other objectives, e.g.
+    /// `Weighted([(RawResources, 1.0), (Power, 0.5)])`.
+    Weighted(Vec<(Objective, f64)>),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlanStep {
+    pub recipe: Recipe,
+    /// Crafts per second.
+    pub rate: f64,
+    /// Machine crafting this step, if one is known for the recipe's category.
+    pub machine: Option<String>,
+    /// Machines needed to sustain `rate`, fractional.
+    pub machine_count: f64,
+    pub modules: Vec<String>,
+    pub beacons: Option<BeaconLayout>,
+    /// Beacons needed across all of the step's machines, fractional.
+    pub beacon_count: f64,
+    /// Power drawn by those beacons, in watts.
+    pub beacon_power: f64,
+    /// Electric power drawn by the machines, their drain and the beacons, in watts.
+    pub electric_power: f64,
+    /// Fuel energy burnt per second by burner machines, in watts.
+    pub burner_power: f64,
+    /// Pollution emitted by the machines per minute.
+    pub pollution: f64,
+    /// Combined effect of the modules, beacons and productivity bonuses, as the game
+    /// clamps it.
+    pub effect: ModuleEffect,
+}
+
+impl PlanStep {
+    /// Whole machines to build for the step.
+    pub fn built_machines(&self) -> f64 {
+        (self.machine_count - RATE_EPSILON).ceil().max(0f64)
+    }
+
+    /// Fraction of the time the built machines stand idle.
+    pub fn idle_fraction(&self) -> f64 {
+        match self.built_machines() {
+            built if built > 0f64 => 1f64 - self.machine_count / built,
+            _ => 0f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProductionPlan {
+    pub goals: Vec<(ProductId, f64)>,
+    pub steps: Vec<PlanStep>,
+    /// Items without a recipe, consumed per second.
+    pub raw_inputs: BTreeMap<ProductId, f64>,
+    /// Items produced beyond what the plan consumes, per second.
+    pub byproducts: BTreeMap<ProductId, f64>,
+}
+
+impl ProductionPlan {
+    /// Total electric power drawn, in watts.
+    pub fn electric_power(&self) -> f64 {
+        self.steps.iter().map(|s| s.electric_power).sum()
+    }
+
+    /// Total fuel energy burnt per second, in watts.
+    pub fn burner_power(&self) -> f64 {
+        self.steps.iter().map(|s| s.burner_power).sum()
+    }
+
+    /// Fraction of the time all built machines together stand idle.
+    pub fn idle_fraction(&self) -> f64 {
+        let built: f64 = self.steps.iter().map(PlanStep::built_machines).sum();
+        let busy: f64 = self.steps.iter().map(|s| s.machine_count).sum();
+        if built > 0f64 {
+            1f64 - busy / built
+        } else {
+            0f64
+        }
+    }
+
+    /// Total pollution per minute.
+    pub fn pollution(&self) -> f64 {
+        self.steps.iter().map(|s| s.pollution).sum()
+    }
+
+    /// Net rate of each item a step produces (positive) or consumes (negative).
+    pub(crate) fn step_flows(step: &PlanStep) -> BTreeMap<&str, f64> {
+        let mut flows = BTreeMap::new();
+        let items = step
+            .recipe
+            .ingredients
+            .iter()
+            .chain(step.recipe.results.iter());
+        for item in items {
+            flows.entry(&*item.name).or_insert_with(|| {
+                step.rate * step.recipe.net_amount(&item.name, step.effect.productivity)
+            });
+        }
+        flows
+    }
+
+    /// How much of each produced item is made for each goal, per second, in the order of
+    /// `goals`.
+    ///
+    /// An item is split between the steps consuming it in proportion to what they consume,
+    /// and each step passes its share of the goals on to its ingredients. The results of a
+    /// step share its attribution in proportion to their amounts; whatever ends up as a
+    /// byproduct is attributed to no goal.
+    pub fn attribution(&self) -> Result<BTreeMap<ProductId, Vec<f64>>, String> {
+        let flows: Vec<_> = self.steps.iter().map(Self::step_flows).collect();
+        let mut produced = BTreeMap::<&str, f64>::new();
+        for (&item, &rate) in flows.iter().flatten() {
+            if rate > 0f64 {
+                *produced.entry(item).or_default() += rate;
+            }
+        }
+        let items: Vec<&str> = produced.keys().copied().collect();
+        let index: HashMap<&str, usize> = items.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+
+        // share[i] = direct[i] + sum over consumers of (consumed / produced[i]) * share of
+        // the consumer, where a consumer's share is the amount-weighted share of its results
+        let n = items.len();
+        let mut system = vec![vec![0f64; n]; n];
+        for (i, row) in system.iter_mut().enumerate() {
+            row[i] = 1f64;
+        }
+        for step_flows in flows.iter() {
+            let output: f64 = step_flows.values().filter(|&&r| r > 0f64).sum();
+            for (&consumed, &c) in step_flows.iter().filter(|(_, &r)| r < 0f64) {
+                let i = match index.get(consumed) {
+                    Some(&i) => i,
+                    None => continue,
+                };
+                for (&result, &o) in step_flows.iter().filter(|(_, &r)| r > 0f64) {
+                    system[i][index[result]] -= -c / produced[consumed] * o / output;
+                }
+            }
+        }
+
+        let mut attribution: BTreeMap<ProductId, Vec<f64>> = items
+            .iter()
+            .map(|&item| (item.to_string(), Vec::with_capacity(self.goals.len())))
+            .collect();
+        for (goal, rate) in self.goals.iter() {
+            let mut direct = vec![0f64; n];
+            if let Some(&i) = index.get(&**goal) {
+                direct[i] = rate / produced[&**goal];
+            }
+            let shares = solve_linear_system(system.clone(), direct)?;
+            for (i, share) in shares.into_iter().enumerate() {
+                let item = attribution.get_mut(items[i]).unwrap();
+                item.push(share * produced[items[i]]);
+            }
+        }
+        Ok(attribution)
+    }
+}
+
+/// Formulates a production goal as a linear program over recipe usage rates.
+///
+/// Every item gets a balance constraint `production - consumption + supply - surplus = goal`,
+/// where supply is only available for raw items and surplus only for non-goal items, so
+/// goals are met exactly and any unavoidable byproducts are reported instead of hidden.
+#[derive(Debug, Clone)]
+pub struct Planner<'a> {
+    pub recipes: &'a RecipeMap,
+    pub objective: Objective,
+    /// Cost of one unit of a raw item; items not listed cost 1.
+    pub raw_costs: HashMap<ProductId, f64>,
+    /// Productivity bonus from research applied to each recipe, by recipe name.
+    pub productivity: HashMap<String, f64>,
+    /// Recipe to use for an item: the only one either solver makes it with, overriding
+    /// the default choice of `solve_by_components`. Other recipes may still leave some of
+    /// the item as a byproduct.
+    pub pinned: HashMap<ProductId, String>,
+    /// Recipes that may not be used.
+    pub blacklist: HashSet<String>,
+    /// Machine used for each crafting category, see `MachineMap::choose`.
+    pub machines: HashMap<String, CraftingMachine>,
+    /// Modules inserted in each machine, keyed by recipe name or crafting category.
+    pub modules: HashMap<String, Vec<String>>,
+    /// Known module prototypes, with their effects and limitations.
+    pub module_map: ModuleMap,
+    /// Known beacon prototypes, by name.
+    pub beacons: HashMap<String, Beacon>,
+    /// Beacons around each machine, keyed by recipe name or crafting category.
+    pub beacon_layouts: HashMap<String, BeaconLayout>,
+    /// Items supplied from outside, e.g. by a main bus or by train. They are charged like
+    /// raw items and never produced for the plan.
+    pub inputs: HashSet<ProductId>,
+    /// Items that may not be produced: recipes with them among their results are not used,
+    /// and neither are recipes consuming them unless they are also inputs.
+    pub forbidden: HashSet<ProductId>,
+    /// Mining recipes, see `Resource::recipe`. What they mine is charged as a raw
+    /// resource, and module limitations do not apply to them.
+    pub mined: HashSet<String>,
+}
+
+/// The linear program behind `Planner::solve`. Its variables are the rate of each recipe,
+/// then the supply of each raw item, then the surplus of each non-goal item; its
+/// constraints are the balances of `items`, in order.
+#[derive(Debug, Clone)]
+pub(crate) struct Formulation<'a> {
+    pub recipes: Vec<&'a Recipe>,
+    pub setups: Vec<MachineSetup>,
+    pub items: Vec<ProductId>,
+    /// Indices into `items` of the items that can be supplied.
+    pub raw: Vec<usize>,
+    /// Indices into `items` of the items that may be left over.
+    pub surplus: Vec<usize>,
+    pub lp: LinearProgram,
+}
+
+impl<'a> Formulation<'a> {
+    pub fn supply_start(&self) -> usize {
+        self.recipes.len()
+    }
+
+    pub fn surplus_start(&self) -> usize {
+        self.supply_start() + self.raw.len()
+    }
+
+    /// Reads the plan for `goals` back from the variables `x`.
+    pub fn plan(
+        &self,
+        planner: &Planner,
+        goals: &[(ProductId, f64)],
+        x: &[f64],
+    ) -> Result<ProductionPlan, String> {
+        let steps = self
+            .recipes
+            .iter()
+            .enumerate()
+            .filter(|&(j, _)| x[j] > RATE_EPSILON)
+            .map(|(j, recipe)| planner.step(recipe, x[j]))
+            .collect::<Result<_, _>>()?;
+        let read = |start: usize, indices: &[usize]| {
+            indices
+                .iter()
+                .enumerate()
+                .filter(|&(k, _)| x[start + k] > RATE_EPSILON)
+                .map(|(k, &i)| (self.items[i].clone(), x[start + k]))
+                .collect()
+        };
+        Ok(ProductionPlan {
+            goals: goals.to_vec(),
+            steps,
+            raw_inputs: read(self.supply_start(), &self.raw),
+            byproducts: read(self.surplus_start(), &self.surplus),
+        })
+    }
+}
+
+/// How a recipe is crafted: the machine, its modules, the beacons around it and their
+/// combined effect.
+#[derive(Debug, Clone, Default)]
+pub struct MachineSetup {
+    pub machine: Option<CraftingMachine>,
+    pub modules: Vec<String>,
+    pub beacons: Option<(Beacon, BeaconLayout)>,
+    pub effect: ModuleEffect,
+}
+
+/// The first effect of `effect` not in `allowed`, if any.
+fn rejected_effect(effect: &ModuleEffect, allowed: Option<&Vec<String>>) -> Option<&'static str> {
+    let allowed = allowed?;
+    effect
+        .effect_names()
+        .into_iter()
+        .find(|&e| !allowed.iter().any(|a| a == e))
+}
+
+impl MachineSetup {
+    /// Machines needed per craft per second; recipes without a machine are treated as
+    /// crafted at speed 1.
+    pub fn machines_per_rate(&self, recipe: &Recipe) -> f64 {
+        let speed = self.machine.as_ref().map_or(1f64, |m| m.crafting_speed);
+        recipe.energy_required() / (speed * self.effect.speed_multiplier())
+    }
+
+    /// Electric power drawn or fuel energy burnt per craft per second, in watts. Drain and
+    /// beacons are counted for the fraction of a machine the rate keeps busy.
+    pub fn power_per_rate(&self, recipe: &Recipe) -> f64 {
+        let machine = self.machine.as_ref().map_or(0f64, |m| {
+            let active = m.energy_usage * self.effect.energy_multiplier();
+            let source = &m.energy_source;
+            if source.is_electric() {
+                active + source.drain
+            } else if source.is_burner() {
+                active / source.effectivity
+            } else {
+                0f64
+            }
+        });
+        let beacons = self.beacons.as_ref().map_or(0f64, |(beacon, layout)| {
+            layout.beacons_for(1f64) * beacon.energy_usage
+        });
+        self.machines_per_rate(recipe) * (machine + beacons)
+    }
+
+    /// Pollution per minute per craft per second. Beacons do not pollute.
+    pub fn pollution_per_rate(&self, recipe: &Recipe) -> f64 {
+        let emissions = self
+            .machine
+            .as_ref()
+            .map_or(0f64, |m| m.energy_source.emissions_per_minute);
+        self.machines_per_rate(recipe)
+            * emissions
+            * self.effect.emissions_multiplier()
+            * recipe.emissions_multiplier
+    }
+}
+
+impl<'a> Planner<'a> {
+    pub fn new(recipes: &'a RecipeMap) -> Self {
+        Planner {
+            recipes,
+            objective: Objective::RawResources,
+            raw_costs: HashMap::new(),
+            productivity: HashMap::new(),
+            pinned: HashMap::new(),
+            blacklist: HashSet::new(),
+            machines: HashMap::new(),
+            modules: HashMap::new(),
+            module_map: ModuleMap::default(),
+            beacons: HashMap::new(),
+            beacon_layouts: HashMap::new(),
+            mined: HashSet::new(),
+            inputs: HashSet::new(),
+            forbidden: HashSet::new(),
+        }
+    }
+
+    /// Why `module` cannot go into the machine crafting `recipe`, if it cannot.
+    fn module_problem(
+        &self,
+        recipe: &Recipe,
+        machine: Option<&CraftingMachine>,
+        module: &str,
+    ) -> Option<String> {
+        let module = match self.module_map.0.get(module) {
+            Some(module) => module,
+            None => return Some(format!("Unknown module {}", module)),
+        };
+        if !self.mined.contains(&recipe.name) && !module.allows(&recipe.name) {
+            return Some(format!(
+                "{} cannot be used for {}",
+                module.name, recipe.name
+            ));
+        }
+        let machine = machine?;
+        rejected_effect(&module.effect, machine.allowed_effects.as_ref()).map(|e| {
+            format!(
+                "{} does not accept {} effects from {}",
+                machine.name, e, module.name
+            )
+        })
+    }
+
+    /// The beacons around the machine crafting `recipe` and the effect they transmit to it.
+    fn beacon_setup(
+        &self,
+        recipe: &Recipe,
+        machine: Option<&CraftingMachine>,
+        layout: Option<&BeaconLayout>,
+    ) -> Result<Option<(Beacon, BeaconLayout, ModuleEffect)>, String> {
+        let layout = match layout {
+            Some(layout) => layout,
+            None => return Ok(None),
+        };
+        let beacon = self
+            .beacons
+            .get(&layout.beacon)
+            .ok_or_else(|| format!("Unknown beacon {}", layout.beacon))?;
+        if layout.modules.len() > beacon.module_slots as usize {
+            return Err(format!(
+                "{} modules given for {} around {} but it has {} slots",
+                layout.modules.len(),
+                beacon.name,
+                recipe.name,
+                beacon.module_slots
+            ));
+        }
+        let mut effect = ModuleEffect::default();
+        for module in layout.modules.iter() {
+            if let Some(problem) = self.module_problem(recipe, machine, module) {
+                return Err(problem);
+            }
+            let module_effect = self.module_map.0[module].effect;
+            if let Some(e) = rejected_effect(&module_effect, beacon.allowed_effects.as_ref()) {
+                return Err(format!(
+                    "{} does not accept {} effects from {}",
+                    beacon.name, e, module
+                ));
+            }
+            effect = effect + module_effect;
+        }
+        let strength = beacon.distribution_effectivity * layout.count as f64;
+        Ok(Some((beacon.clone(), layout.clone(), effect * strength)))
+    }
+
+    /// Works out the machine, modules and effect for `recipe`. Modules assigned to the
+    /// recipe by name must all be usable, while modules assigned to its whole category
+    /// are simply left out where they are not allowed.
+    pub fn setup(&self, recipe: &Recipe) -> Result<MachineSetup, String> {
+        let machine = self.machines.get(&recipe.category);
+        let modules = match self.modules.get(&recipe.name) {
+            Some(modules) => modules.clone(),
+            None => self
+                .modules
+                .get(&recipe.category)
+                .into_iter()
+                .flatten()
+                .filter(|m| self.module_problem(recipe, machine, m).is_none())
+                .cloned()
+                .collect(),
+        };
+        let layout = self
+            .beacon_layouts
+            .get(&recipe.name)
+            .or_else(|| self.beacon_layouts.get(&recipe.category));
+        self.setup_with(recipe, modules, layout)
+    }
+
+    /// Like `setup`, but with the given modules and beacons instead of the configured ones.
+    pub fn setup_with(
+        &self,
+        recipe: &Recipe,
+        modules: Vec<String>,
+        layout: Option<&BeaconLayout>,
+    ) -> Result<MachineSetup, String> {
+        let machine = self.machines.get(&recipe.category);
+        if let Some(problem) = modules
+            .iter()
+            .find_map(|m| self.module_problem(recipe, machine, m))
+        {
+            return Err(problem);
+        }
+        let slots = machine.map_or(0, |m| m.module_slots) as usize;
+        if modules.len() > slots {
+            return Err(format!(
+                "{} modules given for {} but {} has {} slots",
+                modules.len(),
+                recipe.name,
+                machine.map_or("no machine", |m| &*m.name),
+                slots
+            ));
+        }
+
+        let research = ModuleEffect {
+            productivity: self.productivity.get(&recipe.name).copied().unwrap_or(0f64),
+            ..ModuleEffect::default()
+        };
+        let (beacons, transmitted) = match self.beacon_setup(recipe, machine, layout)? {
+            Some((beacon, layout, effect)) => (Some((beacon, layout)), effect),
+            None => (None, ModuleEffect::default()),
+        };
+        let effect = modules
+            .iter()
+            .map(|m| self.module_map.0[m].effect)
+            .fold(research + transmitted, |a, b| a + b)
+            .clamped();
+        Ok(MachineSetup {
+            machine: machine.cloned(),
+            modules,
+            beacons,
+            effect,
+        })
+    }
+
+    fn step(&self, recipe: &Recipe, rate: f64) -> Result<PlanStep, String> {
+        let setup = self.setup(recipe)?;
+        let machine_count = rate * setup.machines_per_rate(recipe);
+        let pollution = setup.pollution_per_rate(recipe) * rate;
+        let (beacons, beacon_count, beacon_power) = match setup.beacons {
+            Some((beacon, layout)) => {
+                let count = layout.beacons_for(machine_count);
+                (Some(layout), count, count * beacon.energy_usage)
+            }
+            None => (None, 0f64, 0f64),
+        };
+        // Drain is paid by every machine built, busy or not.
+        let (electric_power, burner_power) = match &setup.machine {
+            Some(machine) => {
+                let active =
+                    machine_count * machine.energy_usage * setup.effect.energy_multiplier();
+                let source = &machine.energy_source;
+                if source.is_electric() {
+                    (
+                        active + (machine_count - RATE_EPSILON).ceil() * source.drain,
+                        0f64,
+                    )
+                } else if source.is_burner() {
+                    (0f64, active / source.effectivity)
+                } else {
+                    (0f64, 0f64)
+                }
+            }
+            None => (0f64, 0f64),
+        };
+        Ok(PlanStep {
+            recipe: recipe.clone(),
+            rate,
+            machine: setup.machine.as_ref().map(|m| m.name.clone()),
+            machine_count,
+            modules: setup.modules,
+            beacons,
+            beacon_count,
+            beacon_power,
+            electric_power: electric_power + beacon_power,
+            burner_power,
+            pollution,
+            effect: setup.effect,
+        })
+    }
+
+    /// Recipes that can contribute to `goals`, found by walking ingredients backwards.
+    fn relevant_recipes(&self, goals: &[(ProductId, f64)]) -> Vec<&'a Recipe> {
+        let mut recipes = BTreeMap::new();
+        let mut seen = BTreeSet::new();
+        let mut todo: VecDeque<ProductId> = goals.iter().map(|(g, _)| g.clone()).collect();
+        while let Some(item) = todo.pop_front() {
+            if !seen.insert(item.clone()) || self.inputs.contains(&item) {
+                continue;
+            }
+            let pinned = self.pinned.get(&item);
+            for recipe in self
+                .usable_recipes(&item)
+                .filter(|r| pinned.is_none_or(|p| *p == r.name))
+            {
+                if recipes.insert(recipe.name.clone(), recipe).is_none() {
+                    todo.extend(recipe.ingredients.iter().map(|i| i.name.clone()));
+                }
+            }
+        }
+        recipes.into_values().collect()
+    }
+
+    /// Recipes producing `item` that are not blacklisted and neither make nor need anything
+    /// forbidden.
+    fn usable_recipes(&self, item: &str) -> impl Iterator<Item = &'a Recipe> + '_ {
+        let forbidden = move |i: &Ingredient| self.forbidden.contains(&i.name);
+        self.recipes
+            .0
+            .get(item)
+            .into_iter()
+            .flatten()
+            .filter(move |r| {
+                !self.blacklist.contains(&r.name)
+                    && !r.results.iter().any(forbidden)
+                    && !r
+                        .ingredients
+                        .iter()
+                        .any(|i| forbidden(i) && !self.inputs.contains(&i.name))
+            })
+    }
+
+    /// Whether `item` is supplied from outside the plan rather than produced.
+    fn is_raw(&self, item: &str) -> bool {
+        self.inputs.contains(item) || !self.recipes.0.contains_key(item)
+    }
+
+    /// Fails if a goal may neither be produced nor supplied.
+    fn check_goals(&self, goals: &[(ProductId, f64)]) -> Result<(), String> {
+        match goals
+            .iter()
+            .find(|(g, _)| self.forbidden.contains(g) && !self.is_raw(g))
+        {
+            Some((goal, _)) => Err(format!("{} is forbidden and not an input", goal)),
+            None => Ok(()),
+        }
+    }
+
+    /// Fails if a pinned recipe is unknown, blacklisted or does not make its item.
+    fn check_pins(&self) -> Result<(), String> {
+        for (item, name) in self.pinned.iter() {
+            if !self.is_raw(item) && !self.usable_recipes(item).any(|r| &r.name == name) {
+                return Err(format!("Pinned recipe {} cannot make {}", name, item));
+            }
+        }
+        Ok(())
+    }
+
+    fn raw_cost(&self, item: &str) -> f64 {
+        self.raw_costs.get(item).copied().unwrap_or(1f64)
+    }
+
+    /// Cost under `objective` of crafting `recipe` once per second, where `mined` is the
+    /// raw cost of what it mines, if it is a mining recipe.
+    fn recipe_cost(
+        &self,
+        objective: &Objective,
+        recipe: &Recipe,
+        setup: &MachineSetup,
+        mined: f64,
+    ) -> f64 {
+        match objective {
+            Objective::RawResources => mined,
+            Objective::CraftingTime => recipe.energy_required(),
+            Objective::MachineCount => setup.machines_per_rate(recipe),
+            Objective::Power => setup.power_per_rate(recipe) / 1e6,
+            Objective::Weighted(weights) => weights
+                .iter()
+                .map(|(o, w)| w * self.recipe_cost(o, recipe, setup, mined))
+                .sum(),
+        }
+    }
+
+    /// Cost under `objective` of supplying one raw `item` per second.
+    fn supply_cost(&self, objective: &Objective, item: &str) -> f64 {
+        match objective {
+            Objective::RawResources => self.raw_cost(item),
+            Objective::Weighted(weights) => weights
+                .iter()
+                .map(|(o, w)| w * self.supply_cost(o, item))
+                .sum(),
+            _ => 0f64,
+        }
+    }
+
+    /// For each item the plan crafts that other usable recipes could also make, the
+    /// recipes it was made with and the alternatives that were not used.
+    pub fn recipe_choices(
+        &self,
+        plan: &ProductionPlan,
+    ) -> BTreeMap<ProductId, (Vec<String>, Vec<String>)> {
+        let mut choices = BTreeMap::new();
+        for step in plan.steps.iter() {
+            for (item, rate) in ProductionPlan::step_flows(step) {
+                let candidates: Vec<&Recipe> = self.usable_recipes(item).collect();
+                if rate <= 0f64 || candidates.len() < 2 || choices.contains_key(item) {
+                    continue;
+                }
+                let (used, unused): (Vec<&Recipe>, Vec<&Recipe>) =
+                    candidates.into_iter().partition(|r| {
+                        plan.steps.iter().any(|s| {
+                            s.recipe.name == r.name
+                                && ProductionPlan::step_flows(s)
+                                    .get(item)
+                                    .is_some_and(|&r| r > 0f64)
+                        })
+                    });
+                let names =
+                    |recipes: Vec<&Recipe>| recipes.iter().map(|r| r.name.clone()).collect();
+                choices.insert(item.to_string(), (names(used), names(unused)));
+            }
+        }
+        choices
+    }
+
+    pub fn solve(&self, goals: &[(ProductId, f64)]) -> Result<ProductionPlan, String> {
+        let formulation = self.formulate(goals)?;
+        let solution = formulation
+            .lp
+            .solve()
+            .map_err(|e| format!("Could not plan {:?}: {}", goals, e))?;
+        formulation.plan(self, goals, &solution.x)
+    }
+
+    /// The linear program `solve` optimizes, see `Formulation`.
+    pub(crate) fn formulate(&self, goals: &[(ProductId, f64)]) -> Result<Formulation<'a>, String> {
+        self.check_goals(goals)?;
+        self.check_pins()?;
+        let recipes = self.relevant_recipes(goals);
+
+        let mut items = BTreeSet::new();
+        items.extend(goals.iter().map(|(g, _)| g.clone()));
+        for recipe in recipes.iter() {
+            items.extend(recipe.ingredients.iter().map(|i| i.name.clone()));
+            items.extend(recipe.results.iter().map(|i| i.name.clone()));
+        }
+        let items: Vec<ProductId> = items.into_iter().collect();
+        let goal_rates: HashMap<&str, f64> = goals.iter().map(|(g, r)| (&**g, *r)).collect();
+
+        let raw: Vec<usize> = (0..items.len())
+            .filter(|&i| self.is_raw(&items[i]))
+            .collect();
+        let surplus: Vec<usize> = (0..items.len())
+            .filter(|&i| !goal_rates.contains_key(&*items[i]))
+            .collect();
+
+        let setups = recipes
+            .iter()
+            .map(|r| self.setup(r))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let supply_start = recipes.len();
+        let surplus_start = supply_start + raw.len();
+        let mut lp = LinearProgram::new(surplus_start + surplus.len());
+
+        for (j, recipe) in recipes.iter().enumerate() {
+            let mined: f64 = if self.mined.contains(&recipe.name) {
+                recipe
+                    .results
+                    .iter()
+                    .map(|r| self.raw_cost(&r.name) * r.amount as f64 * r.probability)
+                    .sum()
+            } else {
+                0f64
+            };
+            lp.costs[j] = self.recipe_cost(&self.objective, recipe, &setups[j], mined)
+                + TIEBREAK * (mined + recipe.energy_required());
+        }
+        for (k, &i) in raw.iter().enumerate() {
+            lp.costs[supply_start + k] =
+                self.supply_cost(&self.objective, &items[i]) + TIEBREAK * self.raw_cost(&items[i]);
+        }
+        for k in 0..surplus.len() {
+            lp.costs[surplus_start + k] = TIEBREAK;
+        }
+
+        for (i, item) in items.iter().enumerate() {
+            let mut row = vec![0f64; lp.costs.len()];
+            for (j, recipe) in recipes.iter().enumerate() {
+                row[j] = recipe.net_amount(item, setups[j].effect.productivity);
+            }
+            if let Ok(k) = raw.binary_search(&i) {
+                row[supply_start + k] = 1f64;
+            }
+            if let Ok(k) = surplus.binary_search(&i) {
+                row[surplus_start + k] = -1f64;
+            }
+            let goal = goal_rates.get(&**item).copied().unwrap_or(0f64);
+            lp.add_constraint(row, Relation::Eq, goal);
+        }
+
+        Ok(Formulation {
+            recipes,
+            setups,
+            items,
+            raw,
+            surplus,
+            lp,
+        })
+    }
+
+    /// The recipe `solve_by_components` uses for `item`: the pinned one if any, else a
+    /// recipe named after the item, else the one producing the most of it per machine.
+    fn chosen_recipe(&self, item: &str) -> Result<Option<&'a Recipe>, String> {
+        if self.is_raw(item) {
+            return Ok(None);
+        }
+        let candidates: Vec<&'a Recipe> = self.usable_recipes(item).collect();
+        if candidates.is_empty() {
+            return Err(format!("No usable recipe makes {}", item));
+        }
+        if let Some(name) = self.pinned.get(item) {
+            return candidates
+                .into_iter()
+                .find(|r| &r.name == name)
+                .map(Some)
+                .ok_or_else(|| format!("Pinned recipe {} cannot make {}", name, item));
+        }
+        if let Some(recipe) = candidates.iter().find(|r| r.name == item) {
+            return Ok(Some(*recipe));
+        }
+        let mut best: Option<(&'a Recipe, f64)> = None;
+        for recipe in candidates {
+            let setup = self.setup(recipe)?;
+            let per_machine = recipe.net_amount(item, setup.effect.productivity)
+                / setup.machines_per_rate(recipe);
+            if per_machine > best.map_or(0f64, |(_, b)| b) {
+                best = Some((recipe, per_machine));
+            }
+        }
+        Ok(best.map(|(recipe, _)| recipe))
+    }
+
+    /// Solves `goals` with one fixed recipe per item instead of an optimization.
+    ///
+    /// The chosen recipes form a graph with an edge from each producer to its consumers.
+    /// Its strongly connected components are visited consumers first; an acyclic component
+    /// just runs fast enough for its largest demand, while a cycle (kovarex enrichment,
+    /// coal liquefaction) is solved as simultaneous balance equations for the items it is
+    /// chosen for. Byproducts that a component makes for items handled earlier are
+    /// reported as surplus rather than fed back.
+    pub fn solve_by_components(
+        &self,
+        goals: &[(ProductId, f64)],
+    ) -> Result<ProductionPlan, String> {
+        self.check_goals(goals)?;
+        let mut chosen = BTreeMap::<ProductId, &'a Recipe>::new();
+        let mut seen = BTreeSet::new();
+        let mut todo: VecDeque<ProductId> = goals.iter().map(|(g, _)| g.clone()).collect();
+        while let Some(item) = todo.pop_front() {
+            if !seen.insert(item.clone()) {
+                continue;
+            }
+            if let Some(recipe) = self.chosen_recipe(&item)? {
+                todo.extend(recipe.ingredients.iter().map(|i| i.name.clone()));
+                chosen.insert(item, recipe);
+            }
+        }
+
+        let mut graph = Graph::<&'a Recipe, ()>::new();
+        let mut nodes = BTreeMap::new();
+        for recipe in chosen.values() {
+            nodes
+                .entry(recipe.name.clone())
+                .or_insert_with(|| graph.add_node(*recipe));
+        }
+        for recipe in chosen.values() {
+            for ingredient in recipe.ingredients.iter() {
+                if let Some(producer) = chosen.get(&ingredient.name) {
+                    graph.update_edge(nodes[&producer.name], nodes[&recipe.name], ());
+                }
+            }
+        }
+
+        let setups = chosen
+            .values()
+            .map(|r| Ok((r.name.clone(), self.setup(r)?)))
+            .collect::<Result<HashMap<_, _>, String>>()?;
+        let productivity = |r: &Recipe| setups[&r.name].effect.productivity;
+
+        let mut demand = BTreeMap::<ProductId, f64>::new();
+        for (goal, rate) in goals {
+            *demand.entry(goal.clone()).or_default() += rate;
+        }
+        let mut rates = BTreeMap::<String, f64>::new();
+
+        // tarjan_scc yields components in reverse topological order, i.e. consumers first.
+        for component in tarjan_scc(&graph) {
+            let recipes: Vec<&'a Recipe> = component.iter().map(|&n| graph[n]).collect();
+            let items: Vec<&ProductId> = chosen
+                .iter()
+                .filter(|(_, r)| recipes.iter().any(|c| c.name == r.name))
+                .map(|(item, _)| item)
+                .collect();
+            let needed = |item: &ProductId| demand.get(item).copied().unwrap_or(0f64).max(0f64);
+            let cyclic = component.len() > 1 || graph.contains_edge(component[0], component[0]);
+
+            let x: Vec<f64> = if !cyclic {
+                let recipe = recipes[0];
+                vec![items
+                    .iter()
+                    .map(|item| needed(item) / recipe.net_amount(item, productivity(recipe)))
+                    .fold(0f64, f64::max)]
+            } else {
+                let matrix: Vec<Vec<f64>> = items
+                    .iter()
+                    .map(|item| {
+                        recipes
+                            .iter()
+                            .map(|r| r.net_amount(item, productivity(r)))
+                            .collect()
+                    })
+                    .collect();
+                let rhs: Vec<f64> = items.iter().map(|item| needed(item)).collect();
+                if items.len() == recipes.len() {
+                    solve_linear_system(matrix, rhs).map_err(|e| {
+                        format!(
+                            "Cannot balance the cycle {:?}: {}",
+                            recipes.iter().map(|r| &r.name).collect::<Vec<_>>(),
+                            e
+                        )
+                    })?
+                } else {
+                    let mut lp = LinearProgram::new(recipes.len());
+                    lp.costs = recipes
+                        .iter()
+                        .map(|r| setups[&r.name].machines_per_rate(r))
+                        .collect();
+                    for (row, rhs) in matrix.into_iter().zip(rhs) {
+                        lp.add_constraint(row, Relation::Ge, rhs);
+                    }
+                    lp.solve()?.x
+                }
+            };
+
+            for (recipe, rate) in recipes.iter().zip(x) {
+                if rate < -RATE_EPSILON {
+                    return Err(format!(
+                        "The cycle through {} cannot run forwards",
+                        recipe.name
+                    ));
+                }
+                let touched: BTreeSet<&ProductId> = recipe
+                    .ingredients
+                    .iter()
+                    .chain(recipe.results.iter())
+                    .map(|i| &i.name)
+                    .collect();
+                for item in touched {
+                    *demand.entry(item.clone()).or_default() -=
+                        recipe.net_amount(item, productivity(recipe)) * rate;
+                }
+                rates.insert(recipe.name.clone(), rate);
+            }
+        }
+
+        let steps = rates
+            .into_iter()
+            .filter(|&(_, rate)| rate > RATE_EPSILON)
+            .map(|(name, rate)| self.step(graph[nodes[&name]], rate))
+            .collect::<Result<_, _>>()?;
+        let raw_inputs = demand
+            .iter()
+            .filter(|&(_, &rate)| rate > RATE_EPSILON)
+            .map(|(item, &rate)| (item.clone(), rate))
+            .collect();
+        let byproducts = demand
+            .iter()
+            .filter(|&(_, &rate)| rate < -RATE_EPSILON)
+            .map(|(item, &rate)| (item.clone(), -rate))
+            .collect();
+
+        Ok(ProductionPlan {
+            goals: goals.to_vec(),
+            steps,
+            raw_inputs,
+            byproducts,
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_recipe(
+    name: &str,
+    energy_required: f64,
+    ingredients: &[(&str, i64)],
+    results: &[(&str, i64)],
+) -> Recipe {
+    use crate::recipe::Ingredient;
+    let convert = |list: &[(&str, i64)]| {
+        list.iter()
+            .map(|&(name, amount)| Ingredient {
+                name: name.into(),
+                amount,
+                type_: "item".into(),
+                catalyst_amount: None,
+                probability: 1f64,
+            })
+            .collect()
+    };
+    Recipe {
+        name: name.into(),
+        category: "crafting".into(),
+        enabled: true,
+        ingredients: convert(ingredients),
+        speed: 1f64 / energy_required,
+        results: convert(results),
+        emissions_multiplier: 1f64,
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn oil_recipes() -> RecipeMap {
+    RecipeMap::new(vec![
+        test_recipe(
+            "basic-oil-processing",
+            5.0,
+            &[("crude-oil", 100)],
+            &[("petroleum-gas", 45)],
+        ),
+        test_recipe(
+            "advanced-oil-processing",
+            5.0,
+            &[("water", 50), ("crude-oil", 100)],
+            &[("heavy-oil", 25), ("light-oil", 45), ("petroleum-gas", 55)],
+        ),
+        test_recipe(
+            "heavy-oil-cracking",
+            2.0,
+            &[("water", 30), ("heavy-oil", 40)],
+            &[("light-oil", 30)],
+        ),
+        test_recipe(
+            "light-oil-cracking",
+            2.0,
+            &[("water", 30), ("light-oil", 30)],
+            &[("petroleum-gas", 20)],
+        ),
+    ])
+}
+
+#[test]
+fn plan_oil_processing() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+
+    // Advanced processing with full cracking turns 100 crude into 97.5 petroleum.
+    let plan = planner.solve(&[("petroleum-gas".into(), 97.5)])?;
+    let rate = |name: &str| {
+        plan.steps
+            .iter()
+            .find(|s| s.recipe.name == name)
+            .map_or(0.0, |s| s.rate)
+    };
+    assert!((rate("advanced-oil-processing") - 1.0).abs() < 1e-6);
+    assert!((rate("heavy-oil-cracking") - 0.625).abs() < 1e-6);
+    assert!((rate("light-oil-cracking") - 2.125).abs() < 1e-6);
+    assert_eq!(rate("basic-oil-processing"), 0.0);
+    assert!((plan.raw_inputs["crude-oil"] - 100.0).abs() < 1e-6);
+    assert!(plan.byproducts.is_empty());
+
+    // When water is as expensive as crude, basic processing is cheaper.
+    planner.raw_costs.clear();
+    let plan = planner.solve(&[("petroleum-gas".into(), 45.0)])?;
+    assert_eq!(plan.steps.len(), 1);
+    assert_eq!(plan.steps[0].recipe.name, "basic-oil-processing");
+
+    // Asking for heavy oil and petroleum without cracking leaves light oil over.
+    let recipes = RecipeMap::new(
+        oil_recipes()
+            .recipes()
+            .into_iter()
+            .filter(|r| !r.name.ends_with("cracking"))
+            .cloned()
+            .collect(),
+    );
+    let planner = Planner::new(&recipes);
+    let plan = planner.solve(&[("heavy-oil".into(), 25.0), ("petroleum-gas".into(), 55.0)])?;
+    assert!((plan.byproducts["light-oil"] - 45.0).abs() < 1e-6);
+    Ok(())
+}
+
+#[cfg(test)]
+fn uranium_recipes() -> RecipeMap {
+    let mut processing = test_recipe(
+        "uranium-processing",
+        12.0,
+        &[("uranium-ore", 10)],
+        &[("uranium-235", 1), ("uranium-238", 1)],
+    );
+    processing.results[0].probability = 0.007;
+    processing.results[1].probability = 0.993;
+    RecipeMap::new(vec![
+        processing,
+        test_recipe(
+            "kovarex-enrichment-process",
+            60.0,
+            &[("uranium-235", 40), ("uranium-238", 5)],
+            &[("uranium-235", 41), ("uranium-238", 2)],
+        ),
+        test_recipe(
+            "coal-liquefaction",
+            5.0,
+            &[("coal", 10), ("heavy-oil", 25), ("steam", 50)],
+            &[("heavy-oil", 90), ("light-oil", 20), ("petroleum-gas", 10)],
+        ),
+    ])
+}
+
+#[test]
+fn plan_cycles_and_catalysts() -> Result<(), String> {
+    let recipes = uranium_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner
+        .pinned
+        .insert("uranium-235".into(), "kovarex-enrichment-process".into());
+
+    let plan = planner.solve_by_components(&[("uranium-235".into(), 1.0)])?;
+    let rate = |plan: &ProductionPlan, name: &str| {
+        plan.steps
+            .iter()
+            .find(|s| s.recipe.name == name)
+            .map_or(0.0, |s| s.rate)
+    };
+    assert!((rate(&plan, "kovarex-enrichment-process") - 1.0).abs() < 1e-9);
+    assert!((rate(&plan, "uranium-processing") - 3.0 / 0.993).abs() < 1e-9);
+    assert!((plan.raw_inputs["uranium-ore"] - 30.0 / 0.993).abs() < 1e-9);
+    assert!((plan.byproducts["uranium-235"] - 0.021 / 0.993).abs() < 1e-9);
+
+    // Productivity only multiplies the one uranium-235 that is not a catalyst.
+    planner
+        .productivity
+        .insert("kovarex-enrichment-process".into(), 0.1);
+    let plan = planner.solve_by_components(&[("uranium-235".into(), 1.1)])?;
+    assert!((rate(&plan, "kovarex-enrichment-process") - 1.0).abs() < 1e-9);
+    planner.productivity.clear();
+
+    let plan = planner.solve_by_components(&[("heavy-oil".into(), 65.0)])?;
+    assert!((rate(&plan, "coal-liquefaction") - 1.0).abs() < 1e-9);
+    assert!((plan.raw_inputs["coal"] - 10.0).abs() < 1e-9);
+    assert!((plan.byproducts["light-oil"] - 20.0).abs() < 1e-9);
+
+    // The linear program handles the same cycle without being told to use it.
+    let plan = Planner::new(&recipes).solve(&[("uranium-235".into(), 1.0)])?;
+    assert!(rate(&plan, "kovarex-enrichment-process") > 0.9);
+    assert!(plan.raw_inputs["uranium-ore"] < 31.0);
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) fn test_refinery() -> CraftingMachine {
+    use crate::entity::EnergySource;
+    CraftingMachine {
+        name: "oil-refinery".into(),
+        type_: "assembling-machine".into(),
+        crafting_categories: vec!["crafting".into()],
+        crafting_speed: 1.0,
+        module_slots: 3,
+        allowed_effects: None,
+        energy_usage: 420e3,
+        energy_source: EnergySource {
+            type_: "electric".into(),
+            drain: 14e3,
+            effectivity: 1.0,
+            emissions_per_minute: 6.0,
+        },
+        size: (5, 5),
+    }
+}
+
+#[test]
+fn plan_machine_counts() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+    planner.machines.insert("crafting".into(), test_refinery());
+    let plan = planner.solve(&[("petroleum-gas".into(), 97.5)])?;
+    let refinery = plan
+        .steps
+        .iter()
+        .find(|s| s.recipe.name == "advanced-oil-processing")
+        .unwrap();
+    assert_eq!(refinery.machine.as_deref(), Some("oil-refinery"));
+    assert!((refinery.machine_count - 5.0).abs() < 1e-6);
+    assert!((refinery.electric_power - 5.0 * (420e3 + 14e3)).abs() < 1e-3);
+    assert_eq!(refinery.burner_power, 0.0);
+    assert!(plan.electric_power() >= refinery.electric_power);
+    assert!((refinery.pollution - 5.0 * 6.0).abs() < 1e-6);
+    assert!(plan.pollution() >= refinery.pollution);
+    Ok(())
+}
+
+#[test]
+fn plan_pollution() -> Result<(), String> {
+    use crate::module::Module;
+
+    let mut gear = test_recipe(
+        "iron-gear-wheel",
+        0.5,
+        &[("iron-plate", 2)],
+        &[("iron-gear-wheel", 1)],
+    );
+    gear.emissions_multiplier = 2.0;
+    let recipes = RecipeMap::new(vec![gear]);
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_refinery());
+
+    // 1 gear/sec takes half a machine polluting 6/min, doubled by the recipe
+    let plan = planner.solve(&[("iron-gear-wheel".into(), 1.0)])?;
+    assert!((plan.steps[0].pollution - 0.5 * 6.0 * 2.0).abs() < 1e-9);
+    assert!((plan.pollution() - plan.steps[0].pollution).abs() < 1e-9);
+
+    // two modules of +40% consumption and +5% pollution each scale every machine's
+    // pollution by (1 + 0.8)(1 + 0.1); -10% speed and +8% productivity change how many
+    // machines there are
+    planner.module_map = ModuleMap::new(vec![Module {
+        name: "productivity-module".into(),
+        category: "productivity".into(),
+        tier: 1,
+        effect: ModuleEffect {
+            speed: -0.05,
+            consumption: 0.4,
+            productivity: 0.04,
+            pollution: 0.05,
+        },
+        limitation: None,
+    }]);
+    planner.modules.insert(
+        "iron-gear-wheel".into(),
+        vec!["productivity-module".into(); 2],
+    );
+    let plan = planner.solve(&[("iron-gear-wheel".into(), 1.0)])?;
+    let step = &plan.steps[0];
+    let machines = 1.0 / 1.08 * 0.5 / 0.9;
+    assert!((step.machine_count - machines).abs() < 1e-9);
+    assert!((step.pollution - machines * 6.0 * (1.0 + 0.8) * (1.0 + 0.1) * 2.0).abs() < 1e-9);
+
+    // a machine without emissions pollutes nothing, whatever its modules
+    let mut clean = test_refinery();
+    clean.energy_source.emissions_per_minute = 0.0;
+    planner.machines.insert("crafting".into(), clean);
+    let plan = planner.solve(&[("iron-gear-wheel".into(), 1.0)])?;
+    assert_eq!(plan.pollution(), 0.0);
+    Ok(())
+}
+
+/// Tier 1 speed and productivity modules, the latter limited to advanced oil processing.
+#[cfg(test)]
+pub(crate) fn test_modules() -> ModuleMap {
+    use crate::module::Module;
+    use std::iter::FromIterator;
+    ModuleMap::new(vec![
+        Module {
+            name: "speed-module".into(),
+            category: "speed".into(),
+            tier: 1,
+            effect: ModuleEffect {
+                speed: 0.2,
+                consumption: 0.5,
+                ..ModuleEffect::default()
+            },
+            limitation: None,
+        },
+        Module {
+            name: "productivity-module".into(),
+            category: "productivity".into(),
+            tier: 1,
+            effect: ModuleEffect {
+                speed: -0.05,
+                consumption: 0.4,
+                productivity: 0.04,
+                pollution: 0.05,
+            },
+            limitation: Some(HashSet::from_iter(["advanced-oil-processing".into()])),
+        },
+    ])
+}
+
+#[test]
+fn plan_module_effects() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+    planner.machines.insert("crafting".into(), test_refinery());
+    planner.module_map = test_modules();
+
+    planner.modules.insert(
+        "advanced-oil-processing".into(),
+        vec!["speed-module".into(); 3],
+    );
+    let advanced = recipes
+        .recipes()
+        .into_iter()
+        .find(|r| r.name == "advanced-oil-processing")
+        .unwrap();
+    let refinery = planner.setup(advanced)?;
+    assert!((refinery.effect.speed_multiplier() - 1.6).abs() < 1e-9);
+    assert!((refinery.effect.energy_multiplier() - 2.5).abs() < 1e-9);
+    // Faster machines are fewer, but each pollutes in proportion to the energy it uses.
+    let pollution = refinery.pollution_per_rate(advanced);
+    assert!((pollution - 5.0 / 1.6 * 6.0 * 2.5).abs() < 1e-9);
+
+    // Productivity only where the limitation allows it; category-wide modules are
+    // skipped elsewhere, but naming a recipe explicitly is an error.
+    planner.modules.clear();
+    planner
+        .modules
+        .insert("crafting".into(), vec!["productivity-module".into(); 2]);
+    let plan = planner.solve(&[("petroleum-gas".into(), 97.5)])?;
+    for step in plan.steps.iter() {
+        let expected = if step.recipe.name == "advanced-oil-processing" {
+            2
+        } else {
+            0
+        };
+        assert_eq!(step.modules.len(), expected, "{}", step.recipe.name);
+    }
+    planner.modules.insert(
+        "heavy-oil-cracking".into(),
+        vec!["productivity-module".into()],
+    );
+    assert!(planner.solve(&[("petroleum-gas".into(), 97.5)]).is_err());
+
+    planner.modules.clear();
+    planner.modules.insert(
+        "advanced-oil-processing".into(),
+        vec!["speed-module".into(); 4],
+    );
+    assert!(planner.solve(&[("petroleum-gas".into(), 97.5)]).is_err());
+    Ok(())
+}
+
+#[test]
+fn plan_beacons() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+    planner.machines.insert("crafting".into(), test_refinery());
+    planner.beacons.insert(
+        "beacon".into(),
+        Beacon {
+            name: "beacon".into(),
+            distribution_effectivity: 0.5,
+            module_slots: 2,
+            allowed_effects: Some(vec!["speed".into(), "consumption".into()]),
+            energy_usage: 480e3,
+            supply_area_distance: 3.0,
+            size: (3, 3),
+        },
+    );
+    planner.module_map = test_modules();
+
+    // Four beacons with two speed modules each: +0.2 * 2 * 0.5 * 4 = +80% speed.
+    planner.beacon_layouts.insert(
+        "advanced-oil-processing".into(),
+        BeaconLayout {
+            beacon: "beacon".into(),
+            count: 4,
+            modules: vec!["speed-module".into(); 2],
+            shared_by: 2.0,
+        },
+    );
+    let plan = planner.solve(&[("petroleum-gas".into(), 97.5)])?;
+    let refinery = plan
+        .steps
+        .iter()
+        .find(|s| s.recipe.name == "advanced-oil-processing")
+        .unwrap();
+    assert!((refinery.effect.speed_multiplier() - 1.8).abs() < 1e-9);
+    assert!((refinery.machine_count - 5.0 / 1.8).abs() < 1e-6);
+    assert!((refinery.beacon_count - refinery.machine_count * 2.0).abs() < 1e-9);
+    assert!((refinery.beacon_power - refinery.beacon_count * 480e3).abs() < 1e-3);
+
+    planner
+        .beacon_layouts
+        .get_mut("advanced-oil-processing")
+        .unwrap()
+        .modules = vec!["productivity-module".into()];
+    assert!(planner.solve(&[("petroleum-gas".into(), 97.5)]).is_err());
+    Ok(())
+}
+
+#[test]
+fn plan_goal_attribution() -> Result<(), String> {
+    let recipes = RecipeMap::new(vec![
+        test_recipe("iron-plate", 3.2, &[("iron-ore", 1)], &[("iron-plate", 1)]),
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "automation-science-pack",
+            5.0,
+            &[("copper-plate", 1), ("iron-gear-wheel", 1)],
+            &[("automation-science-pack", 1)],
+        ),
+        test_recipe(
+            "transport-belt",
+            0.5,
+            &[("iron-plate", 1), ("iron-gear-wheel", 1)],
+            &[("transport-belt", 2)],
+        ),
+    ]);
+    let planner = Planner::new(&recipes);
+    let plan = planner.solve(&[
+        ("automation-science-pack".into(), 1.0),
+        ("transport-belt".into(), 2.0),
+    ])?;
+    // Both goals share one gear line and one smelting line.
+    assert_eq!(plan.steps.len(), 4);
+    let attribution = plan.attribution()?;
+    let close = |a: &[f64], b: &[f64]| a.iter().zip(b).all(|(a, b)| (a - b).abs() < 1e-6);
+    assert!(close(&attribution["iron-gear-wheel"], &[1.0, 1.0]));
+    assert!(close(&attribution["iron-plate"], &[2.0, 3.0]));
+    assert!(close(&attribution["automation-science-pack"], &[1.0, 0.0]));
+    assert!(close(&attribution["transport-belt"], &[0.0, 2.0]));
+    Ok(())
+}
+
+#[test]
+fn plan_boundaries() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.raw_costs.insert("water".into(), 0.0);
+    planner.forbidden.insert("heavy-oil".into());
+
+    // Without advanced processing, petroleum comes from basic processing alone.
+    let plan = planner.solve(&[("petroleum-gas".into(), 45.0)])?;
+    assert_eq!(plan.steps.len(), 1);
+    assert_eq!(plan.steps[0].recipe.name, "basic-oil-processing");
+
+    // Light oil then needs heavy oil, which must come from outside.
+    let goal = [("light-oil".into(), 30.0)];
+    assert!(planner.solve(&goal).is_err());
+    assert!(planner.solve_by_components(&goal).is_err());
+    planner.inputs.insert("heavy-oil".into());
+    for plan in [planner.solve(&goal)?, planner.solve_by_components(&goal)?].iter() {
+        assert_eq!(plan.steps.len(), 1);
+        assert_eq!(plan.steps[0].recipe.name, "heavy-oil-cracking");
+        assert!((plan.raw_inputs["heavy-oil"] - 40.0).abs() < 1e-6);
+    }
+
+    // Inputs are not produced even where a recipe could make them.
+    planner.forbidden.clear();
+    planner.inputs.insert("petroleum-gas".into());
+    let plan = planner.solve(&[("petroleum-gas".into(), 45.0)])?;
+    assert!(plan.steps.is_empty());
+    assert_eq!(plan.raw_inputs["petroleum-gas"], 45.0);
+    Ok(())
+}
+
+#[test]
+fn plan_objectives() -> Result<(), String> {
+    let recipes = oil_recipes();
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_refinery());
+    let goals = [("petroleum-gas".into(), 45.0)];
+    let uses = |plan: &ProductionPlan, name: &str| plan.steps.iter().any(|s| s.recipe.name == name);
+
+    // With water costing as much as crude, basic processing needs the least raw fluid
+    // while advanced processing and cracking take less crafting time.
+    let plan = planner.solve(&goals)?;
+    assert!(uses(&plan, "basic-oil-processing") && !uses(&plan, "advanced-oil-processing"));
+    let choices = planner.recipe_choices(&plan);
+    assert_eq!(
+        choices["petroleum-gas"],
+        (
+            vec!["basic-oil-processing".to_string()],
+            vec![
+                "advanced-oil-processing".to_string(),
+                "light-oil-cracking".to_string()
+            ]
+        )
+    );
+    for objective in [
+        Objective::CraftingTime,
+        Objective::MachineCount,
+        Objective::Power,
+    ]
+    .iter()
+    {
+        planner.objective = objective.clone();
+        let plan = planner.solve(&goals)?;
+        assert!(!uses(&plan, "basic-oil-processing"), "{:?}", objective);
+    }
+    let weighted = |time: f64| {
+        Objective::Weighted(vec![
+            (Objective::RawResources, 1.0),
+            (Objective::CraftingTime, time),
+        ])
+    };
+    planner.objective = weighted(100.0);
+    assert!(!uses(&planner.solve(&goals)?, "basic-oil-processing"));
+    planner.objective = weighted(1.0);
+    assert!(uses(&planner.solve(&goals)?, "basic-oil-processing"));
+
+    planner.objective = Objective::CraftingTime;
+    planner.blacklist.insert("advanced-oil-processing".into());
+    let plan = planner.solve(&goals)?;
+    assert_eq!(plan.steps.len(), 1);
+    assert!(uses(&plan, "basic-oil-processing"));
+    planner.blacklist.clear();
+
+    // Pinned, petroleum gas only comes from advanced processing, not cracking.
+    planner.objective = Objective::RawResources;
+    planner
+        .pinned
+        .insert("petroleum-gas".into(), "advanced-oil-processing".into());
+    let plan = planner.solve(&goals)?;
+    assert_eq!(plan.steps.len(), 1);
+    assert!(uses(&plan, "advanced-oil-processing"));
+    assert!(plan.byproducts.contains_key("heavy-oil"));
+    Ok(())
+}
+
+#[test]
+fn plan_pins_and_blacklist() -> Result<(), String> {
+    let recipes = RecipeMap::new(vec![
+        test_recipe(
+            "iron-gear-wheel",
+            0.5,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "cast-gears",
+            2.0,
+            &[("steel-plate", 1)],
+            &[("iron-gear-wheel", 3)],
+        ),
+    ]);
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_refinery());
+    let goals = [("iron-gear-wheel".into(), 3.0)];
+
+    // a misspelt or blacklisted pin is named, by both solvers
+    planner
+        .pinned
+        .insert("iron-gear-wheel".into(), "gear-press".into());
+    let error = "Pinned recipe gear-press cannot make iron-gear-wheel";
+    assert_eq!(planner.solve(&goals).unwrap_err(), error);
+    assert_eq!(planner.solve_by_components(&goals).unwrap_err(), error);
+    planner
+        .pinned
+        .insert("iron-gear-wheel".into(), "cast-gears".into());
+    planner.blacklist.insert("cast-gears".into());
+    assert!(planner.solve(&goals).is_err());
+    // pins on inputs do not matter, as nothing makes them
+    planner.inputs.insert("iron-gear-wheel".into());
+    assert_eq!(planner.solve(&goals)?.raw_inputs["iron-gear-wheel"], 3.0);
+    planner.inputs.clear();
+    planner.pinned.clear();
+
+    // one recipe left, taken even though the other costs less
+    let plan = planner.solve(&goals)?;
+    assert_eq!(plan.steps[0].recipe.name, "iron-gear-wheel");
+    // none left: the goal cannot be made
+    planner.blacklist.insert("iron-gear-wheel".into());
+    assert!(planner.solve(&goals).is_err());
+    assert_eq!(
+        planner.solve_by_components(&goals).unwrap_err(),
+        "No usable recipe makes iron-gear-wheel"
+    );
+    Ok(())
+}
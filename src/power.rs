@@ -0,0 +1,334 @@
+//! Sizing the power plant that supplies a production plan.
+
+use crate::entity::{parse_energy, prototypes_of_type};
+use crate::lua_parser::{LuaContext, LuaObject};
+use crate::recipe::ConversionExt;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::convert::TryInto;
+
+/// Temperature of water from offshore pumps, in °C.
+const WATER_TEMPERATURE: f64 = 15f64;
+/// Heat capacity of water and steam, in joules per unit per °C.
+const WATER_HEAT_CAPACITY: f64 = 200f64;
+/// Solar panels produce this fraction of their peak power averaged over a day.
+const SOLAR_AVERAGE: f64 = 0.7;
+/// Accumulator energy needed per watt of demand to last the night: the usual 0.8475
+/// accumulators of 5MJ per 60kW panel supplying 42kW on average.
+const NIGHT_SECONDS: f64 = 0.8475 * 5e6 / 42e3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PowerPlant {
+    /// Boilers burning `PlannerConfig::fuel` and steam engines.
+    Steam,
+    /// Solar panels, with accumulators for the night.
+    Solar,
+    /// Nuclear reactors in two rows, heat exchangers and steam turbines.
+    Nuclear,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Boiler {
+    pub name: String,
+    /// Energy drawn from the fuel, in watts, of which the steam gets `effectivity`.
+    pub power: f64,
+    pub target_temperature: f64,
+    pub effectivity: f64,
+    /// Heat exchangers take heat from reactors instead of burning fuel.
+    pub heated: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Generator {
+    pub name: String,
+    /// Power produced at full steam, in watts.
+    pub power: f64,
+    pub maximum_temperature: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reactor {
+    pub name: String,
+    /// Heat produced without neighbours, in watts.
+    pub power: f64,
+    /// Extra fraction of `power` for each neighbouring reactor.
+    pub neighbour_bonus: f64,
+}
+
+/// Power producing entities and fuels, read from the base mod prototypes.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PowerPrototypes {
+    pub boilers: Vec<Boiler>,
+    pub generators: Vec<Generator>,
+    /// Solar panels and their peak power, in watts.
+    pub solar_panels: Vec<(String, f64)>,
+    /// Accumulators and their capacity, in joules.
+    pub accumulators: Vec<(String, f64)>,
+    pub reactors: Vec<Reactor>,
+    /// Energy of each fuel item, in joules.
+    pub fuel_values: HashMap<String, f64>,
+}
+
+/// Entities making up a power plant, with the fuel it burns per second.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PowerPlantSize {
+    pub entities: Vec<(String, f64)>,
+    pub fuel: Option<(String, f64)>,
+}
+
+fn energy(conts: &mut HashMap<String, LuaObject>, name: &str) -> Result<f64, String> {
+    parse_energy(&conts.field::<String>(name)?)
+}
+
+impl PowerPrototypes {
+    /// Reads power entities from `entity_ctxs` and fuel values from `item_ctx`.
+    pub fn from_contexts(
+        entity_ctxs: &[LuaContext],
+        item_ctx: &LuaContext,
+    ) -> Result<Self, String> {
+        let mut power = PowerPrototypes::default();
+        let types = &[
+            "boiler",
+            "generator",
+            "solar-panel",
+            "accumulator",
+            "reactor",
+        ];
+        for obj in prototypes_of_type(entity_ctxs, types) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            let name: String = conts.field("name")?;
+            let type_: String = conts.field("type")?;
+            let with_name = |e: String| format!("{}: {}", name, e);
+            match &*type_ {
+                "boiler" => {
+                    let mut source: HashMap<String, LuaObject> =
+                        conts.field("energy_source").map_err(with_name)?;
+                    power.boilers.push(Boiler {
+                        power: energy(&mut conts, "energy_consumption").map_err(with_name)?,
+                        target_temperature: conts.field("target_temperature").map_err(with_name)?,
+                        effectivity: source.field("effectivity").unwrap_or(1f64),
+                        heated: source.field::<String>("type").ok().as_deref() == Some("heat"),
+                        name,
+                    })
+                }
+                "generator" => {
+                    let fluid_usage: f64 =
+                        conts.field("fluid_usage_per_tick").map_err(with_name)?;
+                    let maximum_temperature: f64 =
+                        conts.field("maximum_temperature").map_err(with_name)?;
+                    let effectivity: f64 = conts.field("effectivity").unwrap_or(1f64);
+                    power.generators.push(Generator {
+                        power: fluid_usage
+                            * 60f64
+                            * (maximum_temperature - WATER_TEMPERATURE)
+                            * WATER_HEAT_CAPACITY
+                            * effectivity,
+                        maximum_temperature,
+                        name,
+                    })
+                }
+                "solar-panel" => {
+                    let production = energy(&mut conts, "production").map_err(with_name)?;
+                    power.solar_panels.push((name, production));
+                }
+                "accumulator" => {
+                    let mut source: HashMap<String, LuaObject> =
+                        conts.field("energy_source").map_err(with_name)?;
+                    let capacity = energy(&mut source, "buffer_capacity").map_err(with_name)?;
+                    power.accumulators.push((name, capacity));
+                }
+                "reactor" => power.reactors.push(Reactor {
+                    power: energy(&mut conts, "consumption").map_err(with_name)?,
+                    neighbour_bonus: conts.field("neighbour_bonus").unwrap_or(1f64),
+                    name,
+                }),
+                _ => {}
+            }
+        }
+        for obj in prototypes_of_type(std::slice::from_ref(item_ctx), &["item"]) {
+            let mut conts: HashMap<String, LuaObject> = obj.try_into()?;
+            if let Ok(fuel_value) = energy(&mut conts, "fuel_value") {
+                power.fuel_values.insert(conts.field("name")?, fuel_value);
+            }
+        }
+        Ok(power)
+    }
+
+    fn generator_for(&self, boiler: &Boiler) -> Result<&Generator, String> {
+        self.generators
+            .iter()
+            .find(|g| g.maximum_temperature == boiler.target_temperature)
+            .ok_or_else(|| format!("No generator runs on steam from {}", boiler.name))
+    }
+
+    fn fuel_value(&self, fuel: &str) -> Result<f64, String> {
+        self.fuel_values
+            .get(fuel)
+            .copied()
+            .ok_or_else(|| format!("{} is not a fuel", fuel))
+    }
+
+    /// Entities needed to supply `demand` watts of electricity, burning `fuel` if the
+    /// plant burns anything but nuclear fuel cells.
+    pub fn size(
+        &self,
+        plant: PowerPlant,
+        demand: f64,
+        fuel: &str,
+    ) -> Result<PowerPlantSize, String> {
+        match plant {
+            PowerPlant::Steam => {
+                let boiler = self
+                    .boilers
+                    .iter()
+                    .find(|b| !b.heated)
+                    .ok_or_else(|| String::from("No boiler known"))?;
+                let generator = self.generator_for(boiler)?;
+                let burnt = demand / boiler.effectivity / self.fuel_value(fuel)?;
+                Ok(PowerPlantSize {
+                    entities: vec![
+                        (
+                            boiler.name.clone(),
+                            demand / (boiler.power * boiler.effectivity),
+                        ),
+                        (generator.name.clone(), demand / generator.power),
+                    ],
+                    fuel: Some((fuel.into(), burnt)),
+                })
+            }
+            PowerPlant::Solar => {
+                let (panel, production) = self
+                    .solar_panels
+                    .first()
+                    .ok_or_else(|| String::from("No solar panel known"))?;
+                let (accumulator, capacity) = self
+                    .accumulators
+                    .first()
+                    .ok_or_else(|| String::from("No accumulator known"))?;
+                Ok(PowerPlantSize {
+                    entities: vec![
+                        (panel.clone(), demand / (production * SOLAR_AVERAGE)),
+                        (accumulator.clone(), demand * NIGHT_SECONDS / capacity),
+                    ],
+                    fuel: None,
+                })
+            }
+            PowerPlant::Nuclear => {
+                let reactor = self
+                    .reactors
+                    .first()
+                    .ok_or_else(|| String::from("No reactor known"))?;
+                let exchanger = self
+                    .boilers
+                    .iter()
+                    .find(|b| b.heated)
+                    .ok_or_else(|| String::from("No heat exchanger known"))?;
+                let turbine = self.generator_for(exchanger)?;
+                let reactors = nuclear_reactors(reactor, demand);
+                let fuel_cell = "uranium-fuel-cell";
+                Ok(PowerPlantSize {
+                    entities: vec![
+                        (reactor.name.clone(), reactors as f64),
+                        (
+                            exchanger.name.clone(),
+                            demand / (exchanger.power * exchanger.effectivity),
+                        ),
+                        (turbine.name.clone(), demand / turbine.power),
+                    ],
+                    // reactors burn fuel at full rate whatever the demand
+                    fuel: Some((
+                        fuel_cell.into(),
+                        reactors as f64 * reactor.power / self.fuel_value(fuel_cell)?,
+                    )),
+                })
+            }
+        }
+    }
+}
+
+/// Heat from `count` reactors laid out in two rows, each getting the neighbour bonus for
+/// every adjacent reactor.
+fn nuclear_heat(reactor: &Reactor, count: usize) -> f64 {
+    let neighbours = match count {
+        0 | 1 => 0,
+        // 2 x k reactors: k - 1 pairs along each row and k across
+        _ => 2 * (2 * (count / 2 - 1) + count / 2),
+    };
+    reactor.power * (count as f64 + reactor.neighbour_bonus * neighbours as f64)
+}
+
+/// Fewest reactors producing at least `demand` heat: one, or an even number in two rows.
+fn nuclear_reactors(reactor: &Reactor, demand: f64) -> usize {
+    if demand <= 0f64 {
+        return 0;
+    }
+    if demand <= reactor.power {
+        return 1;
+    }
+    let mut count = 2;
+    while nuclear_heat(reactor, count) < demand {
+        count += 2;
+    }
+    count
+}
+
+#[test]
+fn size_power_plants() -> Result<(), String> {
+    let entities = r#"data:extend({
+        { type = "boiler", name = "boiler", energy_consumption = "1.8MW", target_temperature = 165,
+          energy_source = { type = "burner", effectivity = 1, fuel_category = "chemical" } },
+        { type = "boiler", name = "heat-exchanger", energy_consumption = "10MW", target_temperature = 500,
+          energy_source = { type = "heat", max_temperature = 1000 } },
+        { type = "generator", name = "steam-engine", effectivity = 1,
+          fluid_usage_per_tick = 0.5, maximum_temperature = 165 },
+        { type = "generator", name = "steam-turbine", effectivity = 1,
+          fluid_usage_per_tick = 1, maximum_temperature = 500 },
+        { type = "solar-panel", name = "solar-panel", production = "60kW" },
+        { type = "accumulator", name = "accumulator",
+          energy_source = { type = "electric", buffer_capacity = "5MJ" } },
+        { type = "reactor", name = "nuclear-reactor", consumption = "40MW", neighbour_bonus = 1 }
+    })"#;
+    let items = r#"data:extend({
+        { type = "item", name = "coal", fuel_value = "4MJ", stack_size = 50 },
+        { type = "item", name = "uranium-fuel-cell", fuel_value = "8GJ", stack_size = 50 },
+        { type = "item", name = "iron-plate", stack_size = 100 }
+    })"#;
+    let mut entity_ctx = LuaContext::new();
+    entity_ctx
+        .parse_all::<()>(entities)
+        .map_err(|e| format!("{:?}", e))?;
+    let mut item_ctx = LuaContext::new();
+    item_ctx
+        .parse_all::<()>(items)
+        .map_err(|e| format!("{:?}", e))?;
+    let power = PowerPrototypes::from_contexts(&[entity_ctx], &item_ctx)?;
+    assert_eq!(power.fuel_values.len(), 2);
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-6;
+
+    let steam = power.size(PowerPlant::Steam, 18e6, "coal")?;
+    assert_eq!(steam.entities[0].0, "boiler");
+    assert!(close(steam.entities[0].1, 10.0));
+    assert!(close(steam.entities[1].1, 20.0));
+    assert!(close(steam.fuel.unwrap().1, 4.5));
+
+    // Half as effective boilers burn twice the fuel, and twice as many are needed.
+    let mut wasteful = power.clone();
+    wasteful.boilers[0].effectivity = 0.5;
+    let steam = wasteful.size(PowerPlant::Steam, 18e6, "coal")?;
+    assert!(close(steam.entities[0].1, 20.0));
+    assert!(close(steam.entities[1].1, 20.0));
+    assert!(close(steam.fuel.unwrap().1, 9.0));
+
+    let solar = power.size(PowerPlant::Solar, 42e3, "coal")?;
+    assert!(close(solar.entities[0].1, 1.0));
+    assert!(close(solar.entities[1].1, 0.8475));
+    assert!(solar.fuel.is_none());
+
+    // Four reactors in a square make 480MW of heat.
+    let nuclear = power.size(PowerPlant::Nuclear, 400e6, "coal")?;
+    assert_eq!(nuclear.entities[0], ("nuclear-reactor".into(), 4.0));
+    assert!(close(nuclear.entities[1].1, 40.0));
+    assert!(close(nuclear.entities[2].1, 400e6 / 5.82e6));
+    assert!(close(nuclear.fuel.unwrap().1, 4.0 / 200.0));
+    Ok(())
+}
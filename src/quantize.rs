@@ -0,0 +1,161 @@
+//! Turns fractional plans into ones that can be built from whole machines.
+
+use crate::logistics::TransportBelt;
+use crate::planner::ProductionPlan;
+use crate::recipe::ProductId;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+/// How the goals are adjusted before building whole machines for every step.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Rounding {
+    /// Keep the goals and round every step up to whole machines, leaving some idle.
+    WholeMachines,
+    /// Multiply the goals by the smallest whole number up to `max_scale` at which the
+    /// machines are idle no more than `tolerance` of the time, or else by the one leaving
+    /// them least idle.
+    Ratio { max_scale: u32, tolerance: f64 },
+    /// Scale the goals so that the first one fills `count` belts of the given kind.
+    Belts { belt: String, count: f64 },
+}
+
+/// Fraction of the time the machines of `plan` would stand idle with its goals multiplied
+/// by `scale`.
+fn scaled_idle_fraction(plan: &ProductionPlan, scale: f64) -> f64 {
+    let busy: f64 = plan.steps.iter().map(|s| s.machine_count * scale).sum();
+    let built: f64 = plan
+        .steps
+        .iter()
+        .map(|s| (s.machine_count * scale - 1e-9).ceil().max(0f64))
+        .sum();
+    if built > 0f64 {
+        1f64 - busy / built
+    } else {
+        0f64
+    }
+}
+
+/// See `Rounding::Ratio`. Machine counts grow in proportion to the goals, so the
+/// multiples are tried on `plan` without solving again.
+pub fn best_multiple(plan: &ProductionPlan, max_scale: u32, tolerance: f64) -> u32 {
+    let mut best = (1, scaled_idle_fraction(plan, 1f64));
+    for scale in 1..=max_scale.max(1) {
+        let idle = scaled_idle_fraction(plan, scale as f64);
+        if idle <= tolerance {
+            return scale;
+        }
+        if idle < best.1 - 1e-9 {
+            best = (scale, idle);
+        }
+    }
+    best.0
+}
+
+impl Rounding {
+    /// Factor to multiply the goals of `plan` by.
+    pub fn scale(
+        &self,
+        plan: &ProductionPlan,
+        belts: &BTreeMap<String, TransportBelt>,
+    ) -> Result<f64, String> {
+        match self {
+            Rounding::WholeMachines => Ok(1f64),
+            Rounding::Ratio {
+                max_scale,
+                tolerance,
+            } => Ok(best_multiple(plan, *max_scale, *tolerance) as f64),
+            Rounding::Belts { belt, count } => {
+                let belt = belts
+                    .get(belt)
+                    .ok_or_else(|| format!("Unknown belt {}", belt))?;
+                match plan.goals.first() {
+                    Some((_, rate)) if *rate > 0f64 => Ok(count * belt.items_per_second() / rate),
+                    _ => Err(String::from("Filling belts needs a positive goal")),
+                }
+            }
+        }
+    }
+}
+
+pub fn scale_goals(goals: &[(ProductId, f64)], scale: f64) -> Vec<(ProductId, f64)> {
+    goals
+        .iter()
+        .map(|(item, rate)| (item.clone(), rate * scale))
+        .collect()
+}
+
+/// Goal rates the whole machines of `plan` reach when running flat out, which the step
+/// with the least spare capacity limits.
+pub fn achievable_goals(plan: &ProductionPlan) -> Vec<(ProductId, f64)> {
+    let headroom = plan
+        .steps
+        .iter()
+        .filter(|s| s.machine_count > 0f64)
+        .map(|s| s.built_machines() / s.machine_count)
+        .fold(f64::INFINITY, f64::min);
+    let headroom = if headroom.is_finite() { headroom } else { 1f64 };
+    scale_goals(&plan.goals, headroom)
+}
+
+#[test]
+fn quantize_plans() -> Result<(), String> {
+    use crate::logistics::LogisticsPrototypes;
+    use crate::lua_parser::LuaContext;
+    use crate::planner::{test_recipe, Planner};
+    use crate::recipe::RecipeMap;
+
+    let recipes = RecipeMap::new(vec![
+        test_recipe(
+            "iron-gear-wheel",
+            1.0,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "transport-belt",
+            0.5,
+            &[("iron-gear-wheel", 1), ("iron-plate", 1)],
+            &[("transport-belt", 2)],
+        ),
+    ]);
+    let planner = Planner::new(&recipes);
+    let plan = planner.solve(&[("transport-belt".into(), 1.0)])?;
+    // 0.25 belt assemblers and 0.5 gear assemblers
+    assert_eq!(
+        plan.steps.iter().map(|s| s.built_machines()).sum::<f64>(),
+        2.0
+    );
+    assert!((plan.idle_fraction() - 0.625).abs() < 1e-9);
+    assert_eq!(
+        achievable_goals(&plan),
+        vec![("transport-belt".into(), 2.0)]
+    );
+
+    assert_eq!(best_multiple(&plan, 10, 0.0), 4);
+    // 2 and 3 leave a quarter idle, 1 leaves more
+    assert_eq!(best_multiple(&plan, 3, 0.0), 2);
+    let scaled = planner.solve(&scale_goals(&plan.goals, 4.0))?;
+    assert!(scaled.idle_fraction().abs() < 1e-9);
+    assert!(scaled.steps.iter().all(|s| s.idle_fraction().abs() < 1e-9));
+
+    let lua = r#"data:extend({
+        { type = "transport-belt", name = "transport-belt", speed = 0.03125 },
+        { type = "transport-belt", name = "fast-transport-belt", speed = 0.0625 }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let belts = LogisticsPrototypes::from_contexts(&[ctx])?.belts;
+    assert_eq!(belts["transport-belt"].items_per_second(), 15.0);
+    let rounding = Rounding::Belts {
+        belt: "fast-transport-belt".into(),
+        count: 0.5,
+    };
+    assert_eq!(rounding.scale(&plan, &belts)?, 15.0);
+    assert!(Rounding::Belts {
+        belt: "express-transport-belt".into(),
+        count: 1.0
+    }
+    .scale(&plan, &belts)
+    .is_err());
+    Ok(())
+}
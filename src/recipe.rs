@@ -11,6 +11,10 @@ pub struct Ingredient {
     pub name: ProductId,
     pub amount: i64,
     pub type_: String,
+    /// Part of a result that is not boosted by productivity, when given explicitly.
+    pub catalyst_amount: Option<i64>,
+    /// Chance of a result being produced at all, e.g. 0.007 for uranium-235.
+    pub probability: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -21,6 +25,8 @@ pub struct Recipe {
     pub ingredients: Vec<Ingredient>,
     pub speed: ProductsPerSecond,
     pub results: Vec<Ingredient>,
+    /// Scales the pollution of the machine crafting the recipe.
+    pub emissions_multiplier: f64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -28,7 +34,7 @@ pub struct RecipeMap(pub HashMap<ProductId, Vec<Recipe>>);
 
 pub trait ConversionExt {
     type Index: ?Sized;
-    fn field<'a, T: TryFrom<LuaObject, Error = String>>(
+    fn field<T: TryFrom<LuaObject, Error = String>>(
         &mut self,
         index: &Self::Index,
     ) -> Result<T, T::Error>;
@@ -53,6 +59,8 @@ impl TryFrom<LuaObject> for Ingredient {
         let name;
         let amount;
         let type_;
+        let mut catalyst_amount = None;
+        let mut probability = 1f64;
 
         match (array_form, map_form) {
             (Ok((name_, amount_)), Err(_)) => {
@@ -62,8 +70,20 @@ impl TryFrom<LuaObject> for Ingredient {
             }
             (Err(_), Ok(mut map)) => {
                 name = map.field("name")?;
-                amount = map.field("amount").unwrap_or(1);
+                amount = match map.field("amount") {
+                    Ok(amount) => amount,
+                    // fluids mined by pumpjacks give a range instead
+                    Err(_) => match (
+                        map.field::<i64>("amount_min"),
+                        map.field::<i64>("amount_max"),
+                    ) {
+                        (Ok(min), Ok(max)) => (min + max) / 2,
+                        _ => 1,
+                    },
+                };
                 type_ = map.field("type").unwrap_or_else(|_| "item".into());
+                catalyst_amount = map.field("catalyst_amount").ok();
+                probability = map.field("probability").unwrap_or(1f64);
             }
             _ => return Err("Cannot decode ingredient".into()),
         }
@@ -72,6 +92,8 @@ impl TryFrom<LuaObject> for Ingredient {
             name,
             amount,
             type_,
+            catalyst_amount,
+            probability,
         })
     }
 }
@@ -88,6 +110,7 @@ impl TryFrom<LuaObject> for Recipe {
             .field("category")
             .unwrap_or_else(|_| "crafting".into());
 
+        let emissions_multiplier = conts.field("emissions_multiplier").unwrap_or(1f64);
         let recipe: Result<HashMap<String, LuaObject>, String> = conts.field("normal");
 
         let (results, enabled, energy_required, ingredients) = if let Ok(mut recipe) = recipe {
@@ -103,12 +126,14 @@ impl TryFrom<LuaObject> for Recipe {
                                     |(_, c)| Ok((String::try_from(r.clone())?, i64::try_from(c)?)),
                                 )
                             })
-                            .and_then(|(r, c)| {
-                                Ok(vec![Ingredient {
+                            .map(|(r, c)| {
+                                vec![Ingredient {
                                     name: r,
                                     amount: c,
                                     type_: "item".into(),
-                                }])
+                                    catalyst_amount: None,
+                                    probability: 1f64,
+                                }]
                             })
                     },
                     |(_, l)| l.try_into(),
@@ -130,12 +155,14 @@ impl TryFrom<LuaObject> for Recipe {
                                     |(_, c)| Ok((String::try_from(r.clone())?, i64::try_from(c)?)),
                                 )
                             })
-                            .and_then(|(r, c)| {
-                                Ok(vec![Ingredient {
+                            .map(|(r, c)| {
+                                vec![Ingredient {
                                     name: r,
                                     amount: c,
                                     type_: "item".into(),
-                                }])
+                                    catalyst_amount: None,
+                                    probability: 1f64,
+                                }]
                             })
                     },
                     |(_, l)| l.try_into(),
@@ -153,10 +180,63 @@ impl TryFrom<LuaObject> for Recipe {
             ingredients,
             speed: 1f64 / energy_required,
             results,
+            emissions_multiplier,
         })
     }
 }
 
+impl Recipe {
+    /// Amount of `item` passing through a craft unchanged, which productivity does not
+    /// multiply: the explicit `catalyst_amount` of the result if given, otherwise as much
+    /// of the result as the recipe also consumes (40 uranium-235 for kovarex enrichment).
+    pub fn catalyst_amount(&self, item: &str) -> i64 {
+        let explicit: Option<i64> = self
+            .results
+            .iter()
+            .filter(|r| r.name == item)
+            .filter_map(|r| r.catalyst_amount)
+            .reduce(|a, b| a + b);
+        explicit.unwrap_or_else(|| {
+            let produced: i64 = self
+                .results
+                .iter()
+                .filter(|r| r.name == item)
+                .map(|r| r.amount)
+                .sum();
+            let consumed: i64 = self
+                .ingredients
+                .iter()
+                .filter(|i| i.name == item)
+                .map(|i| i.amount)
+                .sum();
+            produced.min(consumed)
+        })
+    }
+
+    /// Expected net amount of `item` produced by one craft, with `productivity` extra on
+    /// the non-catalyst part of the results.
+    pub fn net_amount(&self, item: &str, productivity: f64) -> f64 {
+        let mut catalyst = self.catalyst_amount(item) as f64;
+        let mut produced = 0f64;
+        for result in self.results.iter().filter(|r| r.name == item) {
+            let boosted = (result.amount as f64 - catalyst).max(0f64);
+            catalyst = (catalyst - result.amount as f64).max(0f64);
+            produced += result.probability * (result.amount as f64 + productivity * boosted);
+        }
+        let consumed: i64 = self
+            .ingredients
+            .iter()
+            .filter(|i| i.name == item)
+            .map(|i| i.amount)
+            .sum();
+        produced - consumed as f64
+    }
+
+    pub fn energy_required(&self) -> f64 {
+        1f64 / self.speed
+    }
+}
+
 impl RecipeMap {
     pub fn new(recipes: Vec<Recipe>) -> Self {
         let mut recipe_map = HashMap::<ProductId, Vec<Recipe>>::new();
@@ -172,4 +252,17 @@ impl RecipeMap {
 
         RecipeMap(recipe_map)
     }
+
+    /// Every distinct recipe in the map, sorted by name.
+    pub fn recipes(&self) -> Vec<&Recipe> {
+        let mut recipes: Vec<&Recipe> = self.0.values().flatten().collect();
+        recipes.sort_by(|a, b| a.name.cmp(&b.name));
+        recipes.dedup_by(|a, b| a.name == b.name);
+        recipes
+    }
+
+    /// The recipe called `name`, if any.
+    pub fn get(&self, name: &str) -> Option<&Recipe> {
+        self.0.values().flatten().find(|r| r.name == name)
+    }
 }
@@ -0,0 +1,157 @@
+//! A single HTML page describing a production plan, readable without the planner.
+
+use crate::graph::{escape, ProductionGraph};
+use crate::planner::ProductionPlan;
+use crate::recipe::ProductId;
+use std::collections::BTreeMap;
+use std::io::{self, Write};
+
+const STYLE: &str = "body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; margin-bottom: 1.5em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.6em; }
+td.number { text-align: right; font-variant-numeric: tabular-nums; }
+th { background: #eee; }
+.graph { overflow-x: auto; }";
+
+/// Writes a table of item rates under a heading, or nothing if there are none.
+fn write_rates<W: Write>(
+    f: &mut W,
+    heading: &str,
+    rates: &BTreeMap<ProductId, f64>,
+) -> io::Result<()> {
+    if rates.is_empty() {
+        return Ok(());
+    }
+    writeln!(f, "<h2>{}</h2>", heading)?;
+    writeln!(f, "<table>\n<tr><th>Item</th><th>Per second</th></tr>")?;
+    for (item, rate) in rates.iter() {
+        writeln!(
+            f,
+            "<tr><td>{}</td><td class=\"number\">{:.3}</td></tr>",
+            escape(item),
+            rate
+        )?;
+    }
+    writeln!(f, "</table>")
+}
+
+/// Writes `plan` as a self-contained HTML page: the goals, a table of steps, the raw
+/// inputs, byproducts and totals, and the production graph as inline SVG. Styles are
+/// inline too, so the page needs no other files.
+pub fn write_report<W: Write>(f: &mut W, plan: &ProductionPlan) -> io::Result<()> {
+    let goals: Vec<String> = plan
+        .goals
+        .iter()
+        .map(|(item, rate)| format!("{} @ {:.3}/sec", escape(item), rate))
+        .collect();
+    writeln!(
+        f,
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">"
+    )?;
+    writeln!(f, "<title>Production plan: {}</title>", goals.join(", "))?;
+    writeln!(f, "<style>\n{}\n</style>\n</head>\n<body>", STYLE)?;
+    writeln!(f, "<h1>Production plan</h1>\n<p>{}</p>", goals.join(", "))?;
+
+    writeln!(f, "<h2>Steps</h2>\n<table>")?;
+    writeln!(
+        f,
+        "<tr><th>Recipe</th><th>Crafts per second</th><th>Machine</th><th>Machines</th>\
+         <th>Modules</th><th>Beacons</th><th>Electric MW</th><th>Fuel MW</th>\
+         <th>Pollution per minute</th></tr>"
+    )?;
+    for step in plan.steps.iter() {
+        let beacons = step.beacons.as_ref().map_or(String::new(), |b| {
+            format!(
+                "{:.2} x {} with {}",
+                step.beacon_count,
+                b.beacon,
+                b.modules.join(", ")
+            )
+        });
+        writeln!(
+            f,
+            "<tr><td>{}</td><td class=\"number\">{:.3}</td><td>{}</td>\
+             <td class=\"number\">{:.2}</td><td>{}</td><td>{}</td>\
+             <td class=\"number\">{:.3}</td><td class=\"number\">{:.3}</td>\
+             <td class=\"number\">{:.2}</td></tr>",
+            escape(&step.recipe.name),
+            step.rate,
+            escape(step.machine.as_deref().unwrap_or("-")),
+            step.machine_count,
+            escape(&step.modules.join(", ")),
+            escape(&beacons),
+            step.electric_power / 1e6,
+            step.burner_power / 1e6,
+            step.pollution
+        )?;
+    }
+    writeln!(f, "</table>")?;
+
+    write_rates(f, "Raw inputs", &plan.raw_inputs)?;
+    write_rates(f, "Byproducts", &plan.byproducts)?;
+
+    writeln!(f, "<h2>Totals</h2>\n<table>")?;
+    let machines: f64 = plan.steps.iter().map(|s| s.machine_count).sum();
+    for (name, value) in [
+        ("Machines", machines),
+        ("Electric power, MW", plan.electric_power() / 1e6),
+        ("Fuel burnt, MW", plan.burner_power() / 1e6),
+        ("Pollution per minute", plan.pollution()),
+    ] {
+        writeln!(
+            f,
+            "<tr><th>{}</th><td class=\"number\">{:.3}</td></tr>",
+            name, value
+        )?;
+    }
+    writeln!(f, "</table>")?;
+
+    writeln!(f, "<h2>Graph</h2>\n<div class=\"graph\">")?;
+    ProductionGraph::new(plan).write_svg(f)?;
+    writeln!(f, "</div>\n</body>\n</html>")
+}
+
+#[test]
+fn html_report() -> Result<(), Box<dyn std::error::Error>> {
+    use crate::planner::{test_recipe, Planner};
+    use crate::recipe::RecipeMap;
+
+    // names are escaped everywhere, the SVG included
+    let recipes = RecipeMap::new(vec![test_recipe(
+        "<smelt> & cast",
+        1.0,
+        &[("ore", 1)],
+        &[("\"plate\"", 1), ("slag", 1)],
+    )]);
+    let planner = Planner::new(&recipes);
+    let plan = planner.solve(&[("\"plate\"".into(), 1.0)])?;
+    let mut out = Vec::new();
+    write_report(&mut out, &plan)?;
+    let html = String::from_utf8(out)?;
+    assert!(html.starts_with("<!DOCTYPE html>"));
+    assert!(!html.contains("<smelt>"));
+    assert!(!html.contains("\"plate\""));
+    assert!(html.contains("<td>&lt;smelt&gt; &amp; cast</td>"));
+    assert!(html.contains(">&lt;smelt&gt; &amp; cast</text>"));
+    assert!(html.contains("<title>Production plan: &quot;plate&quot; @ 1.000/sec</title>"));
+    // no machine, so no machine count
+    assert!(html.contains("<td>-</td>"));
+    // steps, raw inputs, byproducts and totals
+    assert_eq!(html.matches("<table>").count(), 4);
+    assert!(html.contains("<h2>Byproducts</h2>"));
+
+    // a goal supplied raw: no steps, nothing but the raw input in the graph
+    let plan = planner.solve(&[("ore".into(), 2.0)])?;
+    let mut out = Vec::new();
+    write_report(&mut out, &plan)?;
+    let html = String::from_utf8(out)?;
+    assert!(html.contains("<th>Pollution per minute</th></tr>\n</table>"));
+    assert!(html.contains("<td>ore</td><td class=\"number\">2.000</td>"));
+    assert!(!html.contains("<h2>Byproducts</h2>"));
+    assert_eq!(html.matches("<ellipse").count(), 1);
+    assert!(!html.contains("<rect"));
+    // nothing to fetch from elsewhere
+    assert!(!html.contains("src="));
+    assert!(!html.contains("<link"));
+    Ok(())
+}
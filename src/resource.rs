@@ -0,0 +1,207 @@
+//! Raw resources, and the mining recipes that let the planner extract them with drills.
+
+use crate::lua_parser::{LuaContext, LuaExpr, LuaObject};
+use crate::recipe::{ConversionExt, Ingredient, Recipe};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::convert::{TryFrom, TryInto};
+
+/// Mining cycles over which a resource's `fluid_amount` is consumed.
+const FLUID_CYCLES: i64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Resource {
+    pub name: String,
+    /// Resource category, matched against the `resource_categories` of mining drills.
+    pub category: String,
+    pub mining_time: f64,
+    pub results: Vec<Ingredient>,
+    /// Fluid needed to mine the resource and how much of it per `FLUID_CYCLES` cycles,
+    /// e.g. sulfuric acid for uranium ore.
+    pub required_fluid: Option<(String, f64)>,
+}
+
+impl TryFrom<LuaObject> for Resource {
+    type Error = String;
+
+    fn try_from(lua: LuaObject) -> Result<Self, Self::Error> {
+        let mut conts: HashMap<String, LuaObject> = lua.try_into()?;
+
+        let name: String = conts.field("name")?;
+        let mut minable: HashMap<String, LuaObject> = conts
+            .field("minable")
+            .map_err(|e| format!("{}: {}", name, e))?;
+        let results = match minable.field("results") {
+            Ok(results) => results,
+            Err(_) => vec![Ingredient {
+                name: minable
+                    .field("result")
+                    .map_err(|e| format!("{}: {}", name, e))?,
+                amount: minable.field("count").unwrap_or(1),
+                type_: "item".into(),
+                catalyst_amount: None,
+                probability: 1f64,
+            }],
+        };
+        let required_fluid = match minable.field::<String>("required_fluid") {
+            Ok(fluid) => Some((fluid, minable.field("fluid_amount").unwrap_or(0f64))),
+            Err(_) => None,
+        };
+        Ok(Resource {
+            category: conts
+                .field("category")
+                .unwrap_or_else(|_| "basic-solid".into()),
+            mining_time: minable
+                .field("mining_time")
+                .map_err(|e| format!("{}: {}", name, e))?,
+            results,
+            required_fluid,
+            name,
+        })
+    }
+}
+
+impl Resource {
+    /// Builds a resource from the parameter table of the base mod's `resource(...)` helper,
+    /// which the ores are defined with instead of plain prototype tables.
+    fn from_parameters(params: LuaObject) -> Result<Self, String> {
+        let mut params: HashMap<String, LuaObject> = params.try_into()?;
+        let name: LuaObject = params
+            .remove("name")
+            .ok_or_else(|| String::from("resource() without a name"))?;
+        let mut minable: HashMap<String, LuaObject> = HashMap::new();
+        minable.insert("result".into(), name.clone());
+        for key in &["mining_time", "required_fluid", "fluid_amount"] {
+            if let Some(value) = params.remove(*key) {
+                minable.insert((*key).into(), value);
+            }
+        }
+        let mut prototype: HashMap<String, LuaObject> = HashMap::new();
+        prototype.insert("name".into(), name);
+        prototype.insert("minable".into(), LuaObject::Map(minable));
+        if let Some(category) = params.remove("category") {
+            prototype.insert("category".into(), category);
+        }
+        Resource::try_from(LuaObject::Map(prototype))
+    }
+
+    /// Every resource in `ctxs`, whether written out or made by `resource(...)`.
+    pub fn from_contexts(ctxs: &[LuaContext]) -> Result<BTreeMap<String, Resource>, String> {
+        let mut resources = BTreeMap::new();
+        for group in ctxs.iter().flat_map(|ctx| ctx.data_extends.iter()) {
+            if let LuaObject::Array(objs) = group.clone().simplify() {
+                for obj in objs {
+                    let resource = match &obj {
+                        LuaObject::Map(map)
+                            if map.get("type") == Some(&LuaObject::Str("resource".into())) =>
+                        {
+                            Resource::try_from(obj)?
+                        }
+                        LuaObject::Expr(expr) => match &**expr {
+                            LuaExpr::Funcall(name, args) if name[..] == ["resource"] => {
+                                match args.first() {
+                                    Some(LuaExpr::Literal(params)) => {
+                                        Resource::from_parameters(params.clone().simplify())?
+                                    }
+                                    _ => continue,
+                                }
+                            }
+                            _ => continue,
+                        },
+                        _ => continue,
+                    };
+                    resources.insert(resource.name.clone(), resource);
+                }
+            }
+        }
+        Ok(resources)
+    }
+
+    /// A recipe in the resource's category that mines it. Resources needing a fluid are
+    /// mined `FLUID_CYCLES` at a time so that the fluid amount stays whole.
+    pub fn recipe(&self) -> Recipe {
+        let (cycles, ingredients) = match &self.required_fluid {
+            Some((fluid, amount)) => (
+                FLUID_CYCLES,
+                vec![Ingredient {
+                    name: fluid.clone(),
+                    amount: amount.round() as i64,
+                    type_: "fluid".into(),
+                    catalyst_amount: None,
+                    probability: 1f64,
+                }],
+            ),
+            None => (1, Vec::new()),
+        };
+        Recipe {
+            name: self.name.clone(),
+            category: self.category.clone(),
+            enabled: true,
+            ingredients,
+            speed: 1f64 / (self.mining_time * cycles as f64),
+            results: self
+                .results
+                .iter()
+                .map(|r| Ingredient {
+                    amount: r.amount * cycles,
+                    ..r.clone()
+                })
+                .collect(),
+            emissions_multiplier: 1f64,
+        }
+    }
+}
+
+#[test]
+fn parse_resources() -> Result<(), String> {
+    use crate::entity::MachineMap;
+    use crate::planner::{test_recipe, Planner};
+    use crate::recipe::RecipeMap;
+
+    let lua = r#"data:extend({
+        resource({ name = "iron-ore", order = "b", mining_time = 1 }, { base_density = 10 }),
+        resource({ name = "uranium-ore", mining_time = 2, fluid_amount = 10,
+                   required_fluid = "sulfuric-acid" }, { base_density = 0.9 }),
+        { type = "resource", name = "crude-oil", category = "basic-fluid",
+          minable = { mining_time = 1, results = { { type = "fluid", name = "crude-oil",
+                      amount_min = 10, amount_max = 10, probability = 1 } } } },
+        { type = "mining-drill", name = "electric-mining-drill", mining_speed = 0.5,
+          resource_categories = { "basic-solid" }, energy_usage = "90kW",
+          module_specification = { module_slots = 3 } }
+    })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let ctxs = [ctx];
+    let resources = Resource::from_contexts(&ctxs)?;
+    assert_eq!(resources.len(), 3);
+    assert_eq!(resources["crude-oil"].category, "basic-fluid");
+    assert_eq!(resources["crude-oil"].results[0].amount, 10);
+
+    let uranium = resources["uranium-ore"].recipe();
+    assert_eq!(uranium.category, "basic-solid");
+    assert_eq!(uranium.ingredients[0].name, "sulfuric-acid");
+    assert_eq!(uranium.net_amount("uranium-ore", 0.0), 10.0);
+    assert_eq!(uranium.net_amount("sulfuric-acid", 0.0), -10.0);
+    assert_eq!(uranium.energy_required(), 20.0);
+
+    // One drill at 0.5 mining speed digs 0.5 ore per second, 0.55 with +10% productivity.
+    let recipes = RecipeMap::new(vec![
+        resources["iron-ore"].recipe(),
+        test_recipe("iron-plate", 3.2, &[("iron-ore", 1)], &[("iron-plate", 1)]),
+    ]);
+    let machines = MachineMap::from_contexts(&ctxs)?;
+    let mut planner = Planner::new(&recipes);
+    planner.machines = machines.choose(&HashMap::new())?;
+    planner.mined.insert("iron-ore".into());
+    planner.productivity.insert("iron-ore".into(), 0.1);
+    let plan = planner.solve(&[("iron-plate".into(), 1.1)])?;
+    let drills = plan
+        .steps
+        .iter()
+        .find(|s| s.recipe.name == "iron-ore")
+        .unwrap();
+    assert_eq!(drills.machine.as_deref(), Some("electric-mining-drill"));
+    assert!((drills.machine_count - 2.0).abs() < 1e-6);
+    assert!(plan.raw_inputs.is_empty());
+    Ok(())
+}
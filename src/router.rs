@@ -0,0 +1,591 @@
+//! Finding where to lay belts between the ports of a layout, going around what is in the
+//! way or under it with underground belts.
+//!
+//! The search is A* over the tile items reach next and the way they move. Belts keep
+//! the lanes of their items through straight runs, turns and underground belts, so a
+//! route either carries both lanes head-on into its port or puts everything onto one
+//! lane of a belt from the side. Tiles where other belts would push items onto the
+//! route are avoided, as they would turn the route's curves into side-loads.
+
+use crate::blueprint::Direction;
+use crate::layout::{step, Connection, Kind, Layout, LayoutConfig, Placed, Port, Tile};
+use crate::logistics::LogisticsPrototypes;
+use crate::recipe::ProductId;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+
+/// Cost of a belt carrying items straight on. Turns cost more, so that of two routes of
+/// the same length the one with fewer turns wins.
+const BELT_COST: u32 = 2;
+const TURN_COST: u32 = 3;
+/// Cost of an underground belt pair over that of belts along the same tiles.
+const UNDERGROUND_COST: u32 = 4;
+
+/// Side of a belt, looking the way it moves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lane {
+    Left,
+    Right,
+}
+
+/// Items reaching a tile moving some way, with nothing placed on the tile yet.
+type State = (Tile, Direction);
+
+/// What is placed on the tile of a state to reach the next one.
+#[derive(Debug, Clone, Copy)]
+enum Move {
+    Belt(Direction),
+    /// An underground belt going in on the tile and coming out this many tiles on.
+    Underground(i32),
+}
+
+/// Cheapest cost found to each state, with the state and move it was reached from.
+type Searched = HashMap<State, (u32, Option<(State, Move)>)>;
+
+/// Whether `entity` puts items onto the tile next to it in `direction`.
+fn feeds(entity: &Placed, direction: Direction) -> bool {
+    entity.moves_items()
+        && entity.direction == direction
+        && entity.kind != Kind::Underground { input: true }
+}
+
+/// Lays belts of one kind, with the underground belts and splitters of the same tier.
+#[derive(Debug, Clone)]
+pub struct Router<'a> {
+    logistics: &'a LogisticsPrototypes,
+    pub belt: String,
+    pub underground: String,
+    pub splitter: String,
+    /// Farthest an underground belt exit can be from its entrance, in tiles.
+    pub max_distance: i32,
+}
+
+impl<'a> Router<'a> {
+    pub fn new(logistics: &'a LogisticsPrototypes, config: &LayoutConfig) -> Result<Self, String> {
+        if !logistics.belts.contains_key(&config.belt) {
+            return Err(format!("Unknown belt {}", config.belt));
+        }
+        let underground = logistics
+            .undergrounds
+            .get(&config.underground)
+            .ok_or_else(|| format!("Unknown underground belt {}", config.underground))?;
+        if !logistics.splitters.contains_key(&config.splitter) {
+            return Err(format!("Unknown splitter {}", config.splitter));
+        }
+        Ok(Router {
+            logistics,
+            belt: config.belt.clone(),
+            underground: config.underground.clone(),
+            splitter: config.splitter.clone(),
+            max_distance: underground.max_distance as i32,
+        })
+    }
+
+    /// Tile of the last belt of a route to `to`, and the way it faces: into the port
+    /// with both lanes, or onto `lane` of the belt at the port's tile from its side.
+    fn goal(&self, layout: &Layout, to: &Port, lane: Option<Lane>) -> Result<State, String> {
+        let lane = match lane {
+            Some(lane) => lane,
+            None => return Ok((to.tile, to.direction)),
+        };
+        let belt = layout
+            .at(to.tile)
+            .filter(|e| e.kind == Kind::Belt)
+            .ok_or_else(|| format!("No belt at {:?} to put {} on", to.tile, to.item))?;
+        // a belt fed only from the side turns instead of taking items onto one lane
+        let fed = layout
+            .at(step(to.tile, belt.direction, -1))
+            .is_some_and(|e| feeds(e, belt.direction));
+        if !fed {
+            return Err(format!(
+                "The belt at {:?} is not fed from behind, so it cannot be side-loaded",
+                to.tile
+            ));
+        }
+        let direction = match lane {
+            Lane::Left => belt.direction.clockwise(),
+            Lane::Right => belt.direction.counter_clockwise(),
+        };
+        Ok((step(to.tile, direction, -1), direction))
+    }
+
+    /// Belts and underground belts carrying the items leaving `from` to `to`, on both
+    /// lanes or on one `lane` of the belt at `to`, without touching anything in `layout`.
+    pub fn route(
+        &self,
+        layout: &Layout,
+        from: &Port,
+        to: &Port,
+        lane: Option<Lane>,
+    ) -> Result<Vec<Placed>, String> {
+        let no_route = || {
+            format!(
+                "No route for {} from {:?} to {:?}",
+                from.item, from.tile, to.tile
+            )
+        };
+        let (goal, facing) = self.goal(layout, to, lane)?;
+
+        // keep the search near the layout, so that it ends when there is no way through
+        let margin = self.max_distance + 2;
+        let ((x0, y0), (x1, y1)) = layout.bounds().unwrap_or((goal, goal));
+        let (x0, x1) = (
+            x0.min(from.tile.0).min(goal.0),
+            x1.max(from.tile.0).max(goal.0),
+        );
+        let (y0, y1) = (
+            y0.min(from.tile.1).min(goal.1),
+            y1.max(from.tile.1).max(goal.1),
+        );
+        let inside = |t: Tile| {
+            (x0 - margin..=x1 + margin).contains(&t.0) && (y0 - margin..=y1 + margin).contains(&t.1)
+        };
+        // free, kept free for the belts of other ports, and with no other belt pushing
+        // items onto it
+        let usable = |t: Tile| {
+            inside(t)
+                && layout.at(t).is_none()
+                && !layout
+                    .ports
+                    .iter()
+                    .any(|p| p.tile == t && p != from && p != to)
+                && [
+                    Direction::North,
+                    Direction::East,
+                    Direction::South,
+                    Direction::West,
+                ]
+                .iter()
+                .all(|&d| layout.at(step(t, d, -1)).is_none_or(|e| !feeds(e, d)))
+        };
+        if layout.at(from.tile).is_some() || !usable(goal) {
+            return Err(no_route());
+        }
+        let heuristic =
+            |t: Tile| BELT_COST * ((t.0 - goal.0).unsigned_abs() + (t.1 - goal.1).unsigned_abs());
+
+        let start = (from.tile, from.direction);
+        let mut best = Searched::new();
+        best.insert(start, (0, None));
+        let mut open = BinaryHeap::new();
+        open.push(Reverse((heuristic(start.0), 0, start)));
+        while let Some(Reverse((_, cost, state))) = open.pop() {
+            if cost > best[&state].0 {
+                continue;
+            }
+            let (tile, direction) = state;
+            if tile == goal && direction != facing.opposite() {
+                return self.placed(layout, &best, state, facing, from);
+            }
+
+            let mut moves = Vec::new();
+            for turn in [
+                direction,
+                direction.clockwise(),
+                direction.counter_clockwise(),
+            ] {
+                let cost = if turn == direction {
+                    BELT_COST
+                } else {
+                    TURN_COST
+                };
+                moves.push(((step(tile, turn, 1), turn), cost, Move::Belt(turn)));
+            }
+            for k in 1..=self.max_distance {
+                let exit = step(tile, direction, k);
+                match layout.at(exit) {
+                    // an exit past one of these would pair with it instead
+                    Some(e)
+                        if e.name == self.underground
+                            && (e.direction == direction
+                                || e.direction == direction.opposite()) =>
+                    {
+                        break
+                    }
+                    None if k > 1 && usable(exit) => moves.push((
+                        (step(exit, direction, 1), direction),
+                        BELT_COST * (k as u32 + 1) + UNDERGROUND_COST,
+                        Move::Underground(k),
+                    )),
+                    _ => {}
+                }
+            }
+            for (next, move_cost, m) in moves {
+                let cost = cost + move_cost;
+                if usable(next.0) && best.get(&next).is_none_or(|&(c, _)| cost < c) {
+                    best.insert(next, (cost, Some((state, m))));
+                    open.push(Reverse((cost + heuristic(next.0), cost, next)));
+                }
+            }
+        }
+        Err(no_route())
+    }
+
+    /// The entities along the route found to `end`, checked to carry items from `from`.
+    fn placed(
+        &self,
+        layout: &Layout,
+        best: &Searched,
+        end: State,
+        facing: Direction,
+        from: &Port,
+    ) -> Result<Vec<Placed>, String> {
+        let mut placed = vec![Placed::new(&self.belt, Kind::Belt, end.0, facing)];
+        let mut state = end;
+        while let Some(((tile, direction), m)) = best[&state].1 {
+            match m {
+                Move::Belt(turn) => placed.push(Placed::new(&self.belt, Kind::Belt, tile, turn)),
+                Move::Underground(k) => {
+                    let exit = Kind::Underground { input: false };
+                    let entrance = Kind::Underground { input: true };
+                    placed.push(Placed::new(
+                        &self.underground,
+                        exit,
+                        step(tile, direction, k),
+                        direction,
+                    ));
+                    placed.push(Placed::new(&self.underground, entrance, tile, direction));
+                }
+            }
+            state = (tile, direction);
+        }
+        placed.reverse();
+
+        // the search does not remember its own belts, so a route may cross itself
+        let mut routed = layout.clone();
+        for entity in placed.iter() {
+            routed.place(entity.clone()).map_err(|_| {
+                format!(
+                    "The route for {} from {:?} crosses itself",
+                    from.item, from.tile
+                )
+            })?;
+        }
+        if !routed.follow(from.tile, self.logistics).contains(&end.0) {
+            return Err(format!(
+                "The route for {} from {:?} is broken",
+                from.item, from.tile
+            ));
+        }
+        Ok(placed)
+    }
+
+    /// A splitter dividing the items leaving `port` between two belts, and the ports
+    /// of the two: one where the items would have gone, and one to its right.
+    pub fn split(&self, layout: &Layout, port: &Port) -> Result<(Placed, [Port; 2]), String> {
+        let direction = port.direction;
+        let tiles = [port.tile, step(port.tile, direction.clockwise(), 1)];
+        // not over anything placed, nor where the belt of another port goes
+        if let Some(tile) = tiles.iter().find(|&&t| {
+            layout.at(t).is_some() || layout.ports.iter().any(|p| p != port && p.tile == t)
+        }) {
+            return Err(format!("No room for a splitter at {:?}", tile));
+        }
+        let mut splitter = Placed::new(
+            &self.splitter,
+            Kind::Splitter,
+            (tiles[0].0.min(tiles[1].0), tiles[0].1.min(tiles[1].1)),
+            direction,
+        );
+        splitter.size = match direction {
+            Direction::East | Direction::West => (1, 2),
+            _ => (2, 1),
+        };
+        let ports = tiles.map(|tile| Port {
+            item: port.item.clone(),
+            tile: step(tile, direction, 1),
+            direction,
+            input: false,
+        });
+        Ok((splitter, ports))
+    }
+
+    /// Splits the outputs of `item` in `layout` until there are `wanted` of them or no
+    /// output has room for a splitter, newest branches first, so that the splitters
+    /// chain off to the right rather than into each other's branches.
+    fn split_outputs(&self, layout: &mut Layout, item: &str, wanted: usize) {
+        loop {
+            let outputs: Vec<Port> = layout
+                .ports
+                .iter()
+                .filter(|p| !p.input && p.item == item)
+                .cloned()
+                .collect();
+            if outputs.is_empty() || outputs.len() >= wanted {
+                return;
+            }
+            let split = outputs
+                .iter()
+                .rev()
+                .find_map(|port| Some((port, self.split(layout, port).ok()?)));
+            let (port, (splitter, branches)) = match split {
+                Some(split) => split,
+                None => return,
+            };
+            // the splitter was checked for room
+            layout.place(splitter).unwrap();
+            layout.ports.retain(|p| p != port);
+            layout.ports.extend(branches);
+        }
+    }
+
+    /// Routes each input port of `layout` from an output port of the same item, placing
+    /// the belts and replacing both ports with a connection. Outputs are split when
+    /// their item has more inputs than outputs; a splitter sends everything down one
+    /// branch once the other backs up, so every input gets what it takes as long as the
+    /// output carries enough for all. Each input tries the outputs nearest first, and
+    /// one that cannot be connected stays, with a note saying why.
+    pub fn connect_ports(&self, layout: &mut Layout) {
+        let mut items: Vec<ProductId> = Vec::new();
+        for port in layout.ports.iter().filter(|p| p.input) {
+            if !items.contains(&port.item) {
+                items.push(port.item.clone());
+            }
+        }
+        for item in items {
+            let inputs: Vec<Port> = layout
+                .ports
+                .iter()
+                .filter(|p| p.input && p.item == item)
+                .cloned()
+                .collect();
+            self.split_outputs(layout, &item, inputs.len());
+            for to in inputs {
+                let mut outputs: Vec<Port> = layout
+                    .ports
+                    .iter()
+                    .filter(|p| !p.input && p.item == item)
+                    .cloned()
+                    .collect();
+                if outputs.is_empty() {
+                    layout.notes.push(format!(
+                        "Nothing in the layout puts out the {} going in at {:?}",
+                        item, to.tile
+                    ));
+                    continue;
+                }
+                outputs
+                    .sort_by_key(|p| (p.tile.0 - to.tile.0).abs() + (p.tile.1 - to.tile.1).abs());
+                let mut errors = Vec::new();
+                let mut routed = None;
+                for from in outputs {
+                    match self.route(layout, &from, &to, None) {
+                        Ok(route) => {
+                            routed = Some((from, route));
+                            break;
+                        }
+                        Err(e) => errors.push(e),
+                    }
+                }
+                let (from, route) = match routed {
+                    Some(routed) => routed,
+                    None => {
+                        layout.notes.push(format!(
+                            "The {} going in at {:?} is not connected: {}",
+                            item,
+                            to.tile,
+                            errors.join("; ")
+                        ));
+                        continue;
+                    }
+                };
+                for entity in route {
+                    // the route was checked against the layout
+                    layout.place(entity).unwrap();
+                }
+                layout.ports.retain(|p| p != &from && p != &to);
+                layout.connections.push(Connection {
+                    item: item.clone(),
+                    from: from.tile,
+                    to: to.tile,
+                });
+            }
+        }
+    }
+}
+
+#[test]
+fn route_belts() -> Result<(), String> {
+    use crate::layout::test_logistics;
+
+    // '#' is in the way, '>' and 'v' are belts, 'A' is where items leave a port and
+    // 'B' where they go in
+    let map = |rows: &[&str]| {
+        let mut layout = Layout::default();
+        let (mut a, mut b) = ((0, 0), (0, 0));
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = (x as i32, y as i32);
+                let belt = |d| Placed::new("transport-belt", Kind::Belt, tile, d);
+                match c {
+                    '#' => {
+                        let wall = Kind::Machine { electric: false };
+                        layout
+                            .place(Placed::new("stone-wall", wall, tile, Direction::North))
+                            .unwrap();
+                    }
+                    '>' => layout.place(belt(Direction::East)).unwrap(),
+                    'v' => layout.place(belt(Direction::South)).unwrap(),
+                    'A' => a = tile,
+                    'B' => b = tile,
+                    _ => {}
+                }
+            }
+        }
+        let port = |tile, input| Port {
+            item: "iron-plate".into(),
+            tile,
+            direction: Direction::East,
+            input,
+        };
+        (layout, port(a, false), port(b, true))
+    };
+    let logistics = test_logistics();
+    let router = Router::new(&logistics, &LayoutConfig::default())?;
+    let fast = LayoutConfig {
+        splitter: "fast-splitter".into(),
+        ..LayoutConfig::default()
+    };
+    assert_eq!(
+        Router::new(&logistics, &fast).err(),
+        Some("Unknown splitter fast-splitter".to_string())
+    );
+    let count = |route: &[Placed], kind: Kind| route.iter().filter(|e| e.kind == kind).count();
+    let routed = |layout: &Layout, route: &[Placed], from: Tile, to: Tile| {
+        let mut layout = layout.clone();
+        for entity in route.iter() {
+            layout.place(entity.clone()).unwrap();
+        }
+        layout.follow(from, &logistics).contains(&to)
+    };
+
+    // straight across
+    let (layout, from, to) = map(&["A.....B"]);
+    let route = router.route(&layout, &from, &to, None)?;
+    assert_eq!(count(&route, Kind::Belt), 7);
+    assert!(routed(&layout, &route, from.tile, to.tile));
+
+    // under a wall rather than the long way round
+    let (layout, from, to) = map(&[
+        "...#.....",
+        "A..#....B",
+        "...#.....",
+        "...#.....",
+        "...#.....",
+    ]);
+    let route = router.route(&layout, &from, &to, None)?;
+    assert_eq!(count(&route, Kind::Underground { input: true }), 1);
+    assert!(route.iter().all(|e| e.tile.1 == 1));
+    assert!(routed(&layout, &route, from.tile, to.tile));
+
+    // a belt pushing items onto the way is passed under too, as a belt beside it
+    // would take them on
+    let (layout, from, to) = map(&[".....v....", "A........B"]);
+    let route = router.route(&layout, &from, &to, None)?;
+    assert_eq!(count(&route, Kind::Underground { input: true }), 1);
+    assert!(route.iter().all(|e| e.tile != (5, 1)));
+    assert!(routed(&layout, &route, from.tile, to.tile));
+
+    // too thick to go under, so around
+    let (layout, from, to) = map(&[
+        "..........",
+        "..######..",
+        "A.######.B",
+        "..######..",
+        "..........",
+    ]);
+    let route = router.route(&layout, &from, &to, None)?;
+    assert_eq!(count(&route, Kind::Underground { input: true }), 0);
+    assert!(routed(&layout, &route, from.tile, to.tile));
+
+    // walled in, with nowhere to come up within reach
+    let (layout, from, to) = map(&["#######", "A#####.B", "#######"]);
+    assert!(router.route(&layout, &from, &to, None).is_err());
+
+    // onto the left lane of an east-going belt is from the north, and only onto a belt
+    // that is fed from behind
+    let (layout, from, _) = map(&["A.....", "......", "..>>>>"]);
+    let onto = |x| Port {
+        item: "iron-plate".into(),
+        tile: (x, 2),
+        direction: Direction::East,
+        input: true,
+    };
+    let route = router.route(&layout, &from, &onto(4), Some(Lane::Left))?;
+    let last = route.last().unwrap();
+    assert_eq!((last.tile, last.direction), ((4, 1), Direction::South));
+    assert!(routed(&layout, &route, from.tile, (4, 2)));
+    let route = router.route(&layout, &from, &onto(4), Some(Lane::Right))?;
+    assert_eq!(route.last().unwrap().tile, (4, 3));
+    assert!(router
+        .route(&layout, &from, &onto(2), Some(Lane::Left))
+        .is_err());
+
+    // split one port between two
+    let (mut layout, from, _) = map(&["......", "A.....", "......", "......"]);
+    let (splitter, ports) = router.split(&layout, &from)?;
+    assert_eq!((splitter.tile, splitter.size), ((0, 1), (1, 2)));
+    layout.place(splitter).unwrap();
+    assert_eq!(ports[1].tile, (1, 2));
+    for (port, y) in ports.iter().zip([0, 3]) {
+        let to = Port {
+            tile: (5, y),
+            ..port.clone()
+        };
+        let route = router.route(&layout, port, &to, None)?;
+        for entity in route {
+            layout.place(entity).unwrap();
+        }
+    }
+    assert!(layout.follow(from.tile, &logistics).contains(&(5, 0)));
+    assert!(layout.follow((0, 2), &logistics).contains(&(5, 3)));
+
+    // ports of the same item get joined up
+    let (mut layout, from, to) = map(&["A..#..B"]);
+    layout.ports = vec![to, from];
+    router.connect_ports(&mut layout);
+    assert!(layout.ports.is_empty());
+    assert_eq!(layout.connections.len(), 1);
+    assert!(layout.check(&logistics).is_empty());
+
+    // one output feeding two inputs is split between them
+    let (mut layout, from, to) = map(&["........", "A.......", "........"]);
+    let input = |tile| Port { tile, ..to.clone() };
+    layout.ports = vec![input((7, 0)), input((7, 4)), from];
+    router.connect_ports(&mut layout);
+    assert!(layout.ports.is_empty() && layout.notes.is_empty());
+    assert_eq!(layout.connections.len(), 2);
+    assert!(layout.entities.iter().any(|e| e.kind == Kind::Splitter));
+    assert!(layout.check(&logistics).is_empty());
+
+    // an output that cannot reach the input gives way to one that can
+    let (mut layout, from, to) = map(&["#######", "A#####.B", "#######"]);
+    let open = Port {
+        tile: (0, 5),
+        ..from.clone()
+    };
+    layout.ports = vec![to.clone(), from.clone(), open.clone()];
+    router.connect_ports(&mut layout);
+    assert_eq!(layout.ports, vec![from.clone()]);
+    assert_eq!(layout.connections[0].from, open.tile);
+    assert!(layout.check(&logistics).is_empty());
+
+    // inputs left unconnected stay, each with a note
+    let (mut layout, from, to) = map(&["#######", "A#####.B", "#######"]);
+    let copper = Port {
+        item: "copper-plate".into(),
+        tile: (7, 4),
+        ..to.clone()
+    };
+    layout.ports = vec![to, from, copper];
+    router.connect_ports(&mut layout);
+    assert_eq!(layout.ports.len(), 3);
+    assert!(layout.connections.is_empty());
+    assert_eq!(layout.notes.len(), 2);
+    assert!(layout.notes[0].starts_with("The iron-plate going in at (7, 1) is not connected"));
+    assert_eq!(
+        layout.notes[1],
+        "Nothing in the layout puts out the copper-plate going in at (7, 4)"
+    );
+    Ok(())
+}
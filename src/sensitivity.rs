@@ -0,0 +1,214 @@
+//! Which limits hold a plan back, and what changing one of them would gain.
+
+use crate::planner::{Planner, ProductionPlan, TIEBREAK};
+use crate::recipe::ProductId;
+use crate::simplex::Relation;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+
+/// What a factory has to work with.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Limits {
+    /// Raw items available per second; items not listed are unlimited.
+    pub supply: HashMap<ProductId, f64>,
+    /// Machines available of each kind, by machine name; kinds not listed are unlimited.
+    pub machines: HashMap<String, f64>,
+}
+
+/// The most that can be made within some `Limits`.
+#[derive(Debug, Clone)]
+pub struct Bottlenecks {
+    /// Largest multiple of the goals the limits allow.
+    pub scale: f64,
+    pub plan: ProductionPlan,
+    /// Extra multiple of the goals gained per extra unit of each limit: the shadow prices
+    /// of the limits, which are zero for those with room to spare.
+    pub supply: BTreeMap<ProductId, f64>,
+    pub machines: BTreeMap<String, f64>,
+}
+
+/// A single change to try with `Limits::what_if`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Change {
+    /// More of a limited raw item per second.
+    Supply(ProductId, f64),
+    /// More machines of a limited kind.
+    Machines(String, f64),
+    /// A higher mining productivity bonus, e.g. 0.1 for one more level of research.
+    MiningProductivity(f64),
+    /// A higher productivity bonus for one recipe.
+    Productivity(String, f64),
+}
+
+/// Outcome of `Limits::what_if`, as multiples of the goals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WhatIf {
+    pub before: f64,
+    pub after: f64,
+}
+
+impl WhatIf {
+    pub fn delta(&self) -> f64 {
+        self.after - self.before
+    }
+}
+
+impl Limits {
+    /// Finds the largest multiple of `goals` that can be made, and how much each limit
+    /// holds it back.
+    ///
+    /// This is the program of `Planner::solve` with the goals scaled by a new variable that
+    /// is maximized, and a constraint per limit. The planner's own objective only breaks
+    /// ties between equally productive plans.
+    pub fn bottlenecks(
+        &self,
+        planner: &Planner,
+        goals: &[(ProductId, f64)],
+    ) -> Result<Bottlenecks, String> {
+        let mut formulation = planner.formulate(goals)?;
+        let goal_rates: HashMap<&str, f64> = goals.iter().map(|(g, r)| (&**g, *r)).collect();
+        let scale_var = formulation.lp.costs.len();
+        let lp = &mut formulation.lp;
+        for cost in lp.costs.iter_mut() {
+            *cost *= TIEBREAK;
+        }
+        lp.costs.push(-1f64);
+        for (constraint, item) in lp.constraints.iter_mut().zip(formulation.items.iter()) {
+            let rate = goal_rates.get(&**item).copied().unwrap_or(0f64);
+            constraint.coefficients.push(-rate);
+            constraint.rhs = 0f64;
+        }
+
+        let mut limits = Vec::new();
+        for (k, &i) in formulation.raw.iter().enumerate() {
+            let item = &formulation.items[i];
+            if let Some(&limit) = self.supply.get(item) {
+                let mut row = vec![0f64; lp.costs.len()];
+                row[formulation.recipes.len() + k] = 1f64;
+                lp.add_constraint(row, Relation::Le, limit);
+                limits.push((Some(item.clone()), None));
+            }
+        }
+        for (machine, &limit) in self.machines.iter() {
+            let mut row = vec![0f64; lp.costs.len()];
+            for (j, setup) in formulation.setups.iter().enumerate() {
+                if setup.machine.as_ref().map(|m| &m.name) == Some(machine) {
+                    row[j] = setup.machines_per_rate(formulation.recipes[j]);
+                }
+            }
+            lp.add_constraint(row, Relation::Le, limit);
+            limits.push((None, Some(machine.clone())));
+        }
+
+        let solution = lp
+            .solve()
+            .map_err(|e| format!("Could not find the bottlenecks of {:?}: {}", goals, e))?;
+        let scale = solution.x[scale_var];
+        let scaled: Vec<_> = goals.iter().map(|(g, r)| (g.clone(), r * scale)).collect();
+        let mut bottlenecks = Bottlenecks {
+            scale,
+            plan: formulation.plan(planner, &scaled, &solution.x)?,
+            supply: BTreeMap::new(),
+            machines: BTreeMap::new(),
+        };
+        let duals = &solution.duals[formulation.items.len()..];
+        for (limit, dual) in limits.into_iter().zip(duals.iter()) {
+            // a looser limit lowers the objective, which is minus the scale
+            let gain = -dual;
+            match limit {
+                (Some(item), _) => bottlenecks.supply.insert(item, gain),
+                (_, Some(machine)) => bottlenecks.machines.insert(machine, gain),
+                (None, None) => unreachable!(),
+            };
+        }
+        Ok(bottlenecks)
+    }
+
+    /// Finds the bottlenecks again with `change` applied, to compare the multiples of the
+    /// goals that can be made before and after.
+    pub fn what_if(
+        &self,
+        planner: &Planner,
+        goals: &[(ProductId, f64)],
+        change: &Change,
+    ) -> Result<WhatIf, String> {
+        let mut changed = planner.clone();
+        let mut limits = self.clone();
+        match change {
+            Change::Supply(item, extra) => match limits.supply.get_mut(item) {
+                Some(limit) => *limit += extra,
+                None => return Err(format!("Supply of {} is not limited", item)),
+            },
+            Change::Machines(machine, extra) => match limits.machines.get_mut(machine) {
+                Some(limit) => *limit += extra,
+                None => return Err(format!("{} is not limited", machine)),
+            },
+            Change::MiningProductivity(extra) => {
+                for recipe in planner.mined.iter() {
+                    *changed.productivity.entry(recipe.clone()).or_default() += extra;
+                }
+            }
+            Change::Productivity(recipe, extra) => {
+                *changed.productivity.entry(recipe.clone()).or_default() += extra;
+            }
+        }
+        Ok(WhatIf {
+            before: self.bottlenecks(planner, goals)?.scale,
+            after: limits.bottlenecks(&changed, goals)?.scale,
+        })
+    }
+}
+
+#[test]
+fn find_bottlenecks() -> Result<(), String> {
+    use crate::planner::{test_recipe, test_refinery};
+    use crate::recipe::RecipeMap;
+
+    let recipes = RecipeMap::new(vec![
+        test_recipe(
+            "iron-gear-wheel",
+            1.0,
+            &[("iron-plate", 2)],
+            &[("iron-gear-wheel", 1)],
+        ),
+        test_recipe(
+            "transport-belt",
+            0.5,
+            &[("iron-gear-wheel", 1), ("iron-plate", 1)],
+            &[("transport-belt", 2)],
+        ),
+    ]);
+    let mut planner = Planner::new(&recipes);
+    planner.machines.insert("crafting".into(), test_refinery());
+    let goals = [("transport-belt".into(), 1.0)];
+
+    // each belt per second takes 1.5 plates per second and 0.75 machines
+    let mut limits = Limits::default();
+    limits.supply.insert("iron-plate".into(), 30.0);
+    limits.machines.insert("oil-refinery".into(), 12.0);
+    let bottlenecks = limits.bottlenecks(&planner, &goals)?;
+    assert!((bottlenecks.scale - 16.0).abs() < 1e-6);
+    assert!((bottlenecks.plan.raw_inputs["iron-plate"] - 24.0).abs() < 1e-6);
+    // less the tie-breaking costs of the extra plates
+    assert!((bottlenecks.machines["oil-refinery"] - 1.0 / 0.75).abs() < 1e-4);
+    assert!(bottlenecks.supply["iron-plate"].abs() < 1e-6);
+
+    let more_machines = Change::Machines("oil-refinery".into(), 3.0);
+    let what_if = limits.what_if(&planner, &goals, &more_machines)?;
+    assert!((what_if.delta() - 4.0).abs() < 1e-6);
+    let more_plates = Change::Supply("iron-plate".into(), 10.0);
+    assert!(
+        limits
+            .what_if(&planner, &goals, &more_plates)?
+            .delta()
+            .abs()
+            < 1e-6
+    );
+    let productivity = Change::Productivity("iron-gear-wheel".into(), 0.5);
+    let what_if = limits.what_if(&planner, &goals, &productivity)?;
+    assert!((what_if.after - 12.0 / (0.25 + 0.5 / 1.5)).abs() < 1e-6);
+    let unlimited = Change::Supply("copper-plate".into(), 1.0);
+    assert!(limits.what_if(&planner, &goals, &unlimited).is_err());
+    Ok(())
+}
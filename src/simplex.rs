@@ -0,0 +1,329 @@
+//! A small dense two-phase simplex solver, enough for the few hundred variables and
+//! constraints that a production plan needs.
+
+const EPSILON: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Relation {
+    Eq,
+    Le,
+    Ge,
+}
+
+#[derive(Debug, Clone)]
+pub struct Constraint {
+    pub coefficients: Vec<f64>,
+    pub relation: Relation,
+    pub rhs: f64,
+}
+
+/// Minimize `costs · x` subject to `constraints`, with `x >= 0`.
+#[derive(Debug, Clone, Default)]
+pub struct LinearProgram {
+    pub costs: Vec<f64>,
+    pub constraints: Vec<Constraint>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Solution {
+    pub x: Vec<f64>,
+    pub objective: f64,
+    /// Marginal change of the objective per unit increase of each constraint's `rhs`.
+    pub duals: Vec<f64>,
+}
+
+impl LinearProgram {
+    pub fn new(variables: usize) -> Self {
+        LinearProgram {
+            costs: vec![0.0; variables],
+            constraints: Vec::new(),
+        }
+    }
+
+    pub fn add_constraint(&mut self, coefficients: Vec<f64>, relation: Relation, rhs: f64) {
+        debug_assert_eq!(coefficients.len(), self.costs.len());
+        self.constraints.push(Constraint {
+            coefficients,
+            relation,
+            rhs,
+        });
+    }
+
+    pub fn solve(&self) -> Result<Solution, String> {
+        Tableau::new(self).solve(self)
+    }
+}
+
+/// Column layout: structural variables, then one slack per inequality, then one
+/// artificial per row, then the right hand side.
+struct Tableau {
+    rows: Vec<Vec<f64>>,
+    basis: Vec<usize>,
+    /// +1 or -1 depending on whether the row was negated to make its rhs non-negative.
+    signs: Vec<f64>,
+    structural: usize,
+    artificial_start: usize,
+    width: usize,
+}
+
+impl Tableau {
+    fn new(lp: &LinearProgram) -> Self {
+        let m = lp.constraints.len();
+        let n = lp.costs.len();
+        let slacks = lp
+            .constraints
+            .iter()
+            .filter(|c| c.relation != Relation::Eq)
+            .count();
+        let artificial_start = n + slacks;
+        let width = artificial_start + m + 1;
+
+        let mut rows = Vec::with_capacity(m);
+        let mut signs = Vec::with_capacity(m);
+        let mut basis = Vec::with_capacity(m);
+        let mut slack = n;
+        for (i, c) in lp.constraints.iter().enumerate() {
+            let mut row = vec![0.0; width];
+            row[..n].copy_from_slice(&c.coefficients);
+            match c.relation {
+                Relation::Le => {
+                    row[slack] = 1.0;
+                    slack += 1;
+                }
+                Relation::Ge => {
+                    row[slack] = -1.0;
+                    slack += 1;
+                }
+                Relation::Eq => {}
+            }
+            row[width - 1] = c.rhs;
+            let sign = if c.rhs < 0.0 { -1.0 } else { 1.0 };
+            for v in row.iter_mut() {
+                *v *= sign;
+            }
+            row[artificial_start + i] = 1.0;
+            rows.push(row);
+            signs.push(sign);
+            basis.push(artificial_start + i);
+        }
+
+        Tableau {
+            rows,
+            basis,
+            signs,
+            structural: n,
+            artificial_start,
+            width,
+        }
+    }
+
+    fn pivot(&mut self, row: usize, col: usize) {
+        let p = self.rows[row][col];
+        for v in self.rows[row].iter_mut() {
+            *v /= p;
+        }
+        let pivot_row = self.rows[row].clone();
+        for (i, r) in self.rows.iter_mut().enumerate() {
+            if i == row {
+                continue;
+            }
+            let factor = r[col];
+            if factor.abs() > 0.0 {
+                for (v, p) in r.iter_mut().zip(pivot_row.iter()) {
+                    *v -= factor * p;
+                }
+            }
+        }
+        self.basis[row] = col;
+    }
+
+    fn reduced_costs(&self, costs: &[f64]) -> Vec<f64> {
+        let mut reduced = costs.to_vec();
+        for (row, &b) in self.rows.iter().zip(self.basis.iter()) {
+            let cb = costs[b];
+            if cb != 0.0 {
+                for (d, v) in reduced.iter_mut().zip(row.iter()) {
+                    *d -= cb * v;
+                }
+            }
+        }
+        reduced
+    }
+
+    /// Runs simplex iterations minimizing `costs` over the columns `< allowed`.
+    fn optimize(&mut self, costs: &[f64], allowed: usize) -> Result<(), String> {
+        let rhs = self.width - 1;
+        let mut degenerate_steps = 0;
+        loop {
+            let reduced = self.reduced_costs(costs);
+            // Dantzig's rule normally, Bland's rule once we appear to be stalling so that
+            // degenerate cycles are impossible.
+            let entering = if degenerate_steps < 50 {
+                (0..allowed)
+                    .filter(|&j| reduced[j] < -EPSILON)
+                    .min_by(|&a, &b| reduced[a].partial_cmp(&reduced[b]).unwrap())
+            } else {
+                (0..allowed).find(|&j| reduced[j] < -EPSILON)
+            };
+            let col = match entering {
+                Some(col) => col,
+                None => return Ok(()),
+            };
+
+            let mut leaving: Option<(usize, f64)> = None;
+            for (i, row) in self.rows.iter().enumerate() {
+                if row[col] > EPSILON {
+                    let ratio = row[rhs] / row[col];
+                    leaving = match leaving {
+                        Some((j, best))
+                            if best < ratio - EPSILON
+                                || (ratio - best).abs() <= EPSILON
+                                    && self.basis[j] < self.basis[i] =>
+                        {
+                            Some((j, best))
+                        }
+                        _ => Some((i, ratio)),
+                    };
+                }
+            }
+            let (row, ratio) =
+                leaving.ok_or_else(|| String::from("Linear program is unbounded"))?;
+            if ratio.abs() <= EPSILON {
+                degenerate_steps += 1;
+            } else {
+                degenerate_steps = 0;
+            }
+            self.pivot(row, col);
+        }
+    }
+
+    fn solve(mut self, lp: &LinearProgram) -> Result<Solution, String> {
+        let rhs = self.width - 1;
+
+        // Phase one: minimize the sum of the artificial variables.
+        let mut phase_one = vec![0.0; self.width];
+        for c in phase_one[self.artificial_start..rhs].iter_mut() {
+            *c = 1.0;
+        }
+        self.optimize(&phase_one, rhs)?;
+        let infeasibility: f64 = self
+            .basis
+            .iter()
+            .zip(self.rows.iter())
+            .filter(|(&b, _)| b >= self.artificial_start)
+            .map(|(_, row)| row[rhs])
+            .sum();
+        if infeasibility > 1e-7 {
+            return Err(format!(
+                "Linear program is infeasible (residual {})",
+                infeasibility
+            ));
+        }
+
+        // Drive any artificial left in the basis (at zero) out, if its row is not redundant.
+        for i in 0..self.rows.len() {
+            if self.basis[i] >= self.artificial_start {
+                if let Some(col) =
+                    (0..self.artificial_start).find(|&j| self.rows[i][j].abs() > EPSILON)
+                {
+                    self.pivot(i, col);
+                }
+            }
+        }
+
+        // Phase two: the real objective, never letting artificials re-enter.
+        let mut phase_two = vec![0.0; self.width];
+        phase_two[..self.structural].copy_from_slice(&lp.costs);
+        self.optimize(&phase_two, self.artificial_start)?;
+
+        let mut x = vec![0.0; self.structural];
+        for (row, &b) in self.rows.iter().zip(self.basis.iter()) {
+            if b < self.structural {
+                x[b] = row[rhs];
+            }
+        }
+        let objective = x.iter().zip(lp.costs.iter()).map(|(x, c)| x * c).sum();
+        let duals = (0..self.rows.len())
+            .map(|i| {
+                let col = self.artificial_start + i;
+                let y: f64 = self
+                    .rows
+                    .iter()
+                    .zip(self.basis.iter())
+                    .map(|(row, &b)| phase_two[b] * row[col])
+                    .sum();
+                y * self.signs[i]
+            })
+            .collect();
+
+        Ok(Solution {
+            x,
+            objective,
+            duals,
+        })
+    }
+}
+
+/// Solves the square system `a · x = b` by Gaussian elimination with partial pivoting.
+pub fn solve_linear_system(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Result<Vec<f64>, String> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+            .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+            .filter(|&i| a[i][col].abs() > EPSILON)
+            .ok_or_else(|| String::from("Linear system is singular"))?;
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            if factor != 0.0 {
+                let pivot_row = a[col].clone();
+                for (v, p) in a[row][col..].iter_mut().zip(pivot_row[col..].iter()) {
+                    *v -= factor * p;
+                }
+                b[row] -= factor * b[col];
+            }
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let known: f64 = (row + 1..n).map(|k| a[row][k] * x[k]).sum();
+        x[row] = (b[row] - known) / a[row][row];
+    }
+    Ok(x)
+}
+
+#[test]
+fn simplex_tests() {
+    // max 3x + 2y  s.t.  x + y <= 4, x + 3y <= 6, x <= 3
+    let mut lp = LinearProgram::new(2);
+    lp.costs = vec![-3.0, -2.0];
+    lp.add_constraint(vec![1.0, 1.0], Relation::Le, 4.0);
+    lp.add_constraint(vec![1.0, 3.0], Relation::Le, 6.0);
+    lp.add_constraint(vec![1.0, 0.0], Relation::Le, 3.0);
+    let sol = lp.solve().unwrap();
+    assert!((sol.x[0] - 3.0).abs() < 1e-9 && (sol.x[1] - 1.0).abs() < 1e-9);
+    assert!((sol.objective + 11.0).abs() < 1e-9);
+    assert!((sol.duals[0] + 2.0).abs() < 1e-9);
+    assert!(sol.duals[1].abs() < 1e-9);
+    assert!((sol.duals[2] + 1.0).abs() < 1e-9);
+
+    // min x + y  s.t.  x - y = -2, x + y >= 4
+    let mut lp = LinearProgram::new(2);
+    lp.costs = vec![1.0, 1.0];
+    lp.add_constraint(vec![1.0, -1.0], Relation::Eq, -2.0);
+    lp.add_constraint(vec![1.0, 1.0], Relation::Ge, 4.0);
+    let sol = lp.solve().unwrap();
+    assert!((sol.x[0] - 1.0).abs() < 1e-9 && (sol.x[1] - 3.0).abs() < 1e-9);
+    assert!((sol.duals[1] - 1.0).abs() < 1e-9);
+
+    let mut lp = LinearProgram::new(1);
+    lp.costs = vec![1.0];
+    lp.add_constraint(vec![1.0], Relation::Le, 1.0);
+    lp.add_constraint(vec![1.0], Relation::Ge, 2.0);
+    assert!(lp.solve().is_err());
+
+    let x = solve_linear_system(vec![vec![0.0, 2.0], vec![1.0, 1.0]], vec![4.0, 3.0]).unwrap();
+    assert!((x[0] - 1.0).abs() < 1e-9 && (x[1] - 2.0).abs() < 1e-9);
+    assert!(solve_linear_system(vec![vec![1.0, 1.0], vec![2.0, 2.0]], vec![1.0, 2.0]).is_err());
+}
@@ -0,0 +1,375 @@
+use crate::lua_parser::{LuaContext, LuaObject};
+use crate::recipe::{ConversionExt, Ingredient};
+use petgraph::{
+    algo::{
+        toposort,
+        tred::{dag_to_toposorted_adjacency_list, dag_transitive_reduction_closure},
+    },
+    graph::NodeIndex,
+    visit::IntoNeighbors,
+    Graph,
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    convert::TryFrom,
+    error::Error,
+    io::Write,
+};
+
+/// Science packs in the order they become available, paired with the fill color used for
+/// technologies whose most advanced ingredient is that pack.
+const SCIENCE_PACKS: &[(&str, &str)] = &[
+    ("automation-science-pack", "#f4a6a6"),
+    ("logistic-science-pack", "#a6e3a6"),
+    ("military-science-pack", "#b8b8b8"),
+    ("chemical-science-pack", "#a6cdf4"),
+    ("production-science-pack", "#d3a6f4"),
+    ("utility-science-pack", "#f4e6a6"),
+    ("space-science-pack", "#ffffff"),
+];
+
+#[derive(Debug)]
+pub struct Technology {
+    pub name: String,
+    pub type_: String,
+    pub effects: Vec<LuaObject>,
+    pub prerequisites: Vec<String>,
+    pub ingredient_count: LuaObject,
+    pub ingredients: Vec<Ingredient>,
+    pub ingredient_time: f64,
+}
+
+impl TryFrom<LuaObject> for Technology {
+    type Error = String;
+
+    fn try_from(value: LuaObject) -> Result<Self, Self::Error> {
+        let mut map = HashMap::<String, LuaObject>::try_from(value)?;
+        let name = map
+            .remove_entry("name")
+            .ok_or_else(|| "No key 'name'".into())
+            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
+        let type_ = map
+            .remove_entry("type")
+            .ok_or_else(|| "No key 'type'".into())
+            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
+        let effects = map.remove_entry("effects").map_or_else(
+            || Ok(vec![]),
+            |(_, l)| <_ as TryFrom<LuaObject>>::try_from(l),
+        )?;
+        let prerequisites = map.remove_entry("prerequisites").map_or_else(
+            || Ok(vec![]),
+            |(_, l)| <_ as TryFrom<LuaObject>>::try_from(l),
+        )?;
+
+        let mut unit = map
+            .remove_entry("unit")
+            .ok_or_else(|| "No key 'unit'".into())
+            .and_then(|(_, l)| HashMap::<String, LuaObject>::try_from(l))?;
+        let ingredient_count = unit.remove_entry("count").map_or_else(
+            || {
+                unit.remove_entry("count_formula")
+                    .ok_or_else(|| String::from("No key 'count' or 'count_formula'"))
+                    .map(|(_, l)| l)
+            },
+            |(_, l)| Ok(l),
+        )?;
+        let ingredients = unit
+            .remove_entry("ingredients")
+            .ok_or_else(|| "No key 'ingredients'".into())
+            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
+        let ingredient_time = unit
+            .remove_entry("time")
+            .ok_or_else(|| "No key 'time'".into())
+            .and_then(|(_, l)| <_ as TryFrom<LuaObject>>::try_from(l))?;
+
+        Ok(Technology {
+            name,
+            type_,
+            effects,
+            prerequisites,
+            ingredient_count,
+            ingredients,
+            ingredient_time,
+        })
+    }
+}
+
+impl Technology {
+    /// Index into `SCIENCE_PACKS` of the most advanced pack this technology consumes.
+    pub fn highest_science_pack(&self) -> Option<usize> {
+        self.ingredients
+            .iter()
+            .filter_map(|i| SCIENCE_PACKS.iter().position(|(pack, _)| *pack == i.name))
+            .max()
+    }
+
+    /// Names of the recipes unlocked by researching this technology.
+    pub fn unlocked_recipes(&self) -> impl Iterator<Item = String> + '_ {
+        self.effects.iter().filter_map(|effect| {
+            let mut effect = HashMap::<String, LuaObject>::try_from(effect.clone()).ok()?;
+            let type_: String = effect.field("type").ok()?;
+            if type_ == "unlock-recipe" {
+                effect.field("recipe").ok()
+            } else {
+                None
+            }
+        })
+    }
+
+    fn cost_label(&self) -> String {
+        let count = match &self.ingredient_count {
+            LuaObject::Int(i) => i.to_string(),
+            LuaObject::Float(f) => f.to_string(),
+            LuaObject::Str(formula) => formula.clone(),
+            other => format!("{:?}", other),
+        };
+        let packs = self
+            .ingredients
+            .iter()
+            .map(|i| format!("{} {}", i.amount, i.name.trim_end_matches("-science-pack")))
+            .collect::<Vec<_>>()
+            .join(", ");
+        format!("{} x {}s\\n{}", count, self.ingredient_time, packs)
+    }
+}
+
+#[derive(Debug)]
+pub struct TechTree(pub HashMap<String, Technology>);
+
+impl TechTree {
+    pub fn new(techs: Vec<Technology>) -> Self {
+        TechTree(techs.into_iter().map(|t| (t.name.clone(), t)).collect())
+    }
+
+    /// Collects every technology from the `data:extend` calls of a parsed `technology.lua`,
+    /// skipping any group that fails to convert and returning why alongside the tree.
+    pub fn from_context(ctx: LuaContext) -> (Self, Vec<String>) {
+        let mut techs = Vec::new();
+        let mut skipped = Vec::new();
+        for group in ctx.data_extends {
+            match Vec::<Technology>::try_from(group.simplify()) {
+                Ok(group) => techs.extend(group),
+                Err(e) => skipped.push(e),
+            }
+        }
+        (TechTree::new(techs), skipped)
+    }
+
+    /// Finds the technology whose effects unlock `recipe`, if any.
+    pub fn unlocking(&self, recipe: &str) -> Option<&Technology> {
+        self.0
+            .values()
+            .find(|tech| tech.unlocked_recipes().any(|r| r == recipe))
+    }
+
+    /// The technology itself along with all of its transitive prerequisites.
+    pub fn ancestors(&self, target: &str) -> HashSet<String> {
+        let mut seen = HashSet::new();
+        let mut todo = VecDeque::new();
+        todo.push_back(target.to_string());
+        while let Some(name) = todo.pop_front() {
+            if !seen.insert(name.clone()) {
+                continue;
+            }
+            if let Some(tech) = self.0.get(&name) {
+                todo.extend(tech.prerequisites.iter().cloned());
+            }
+        }
+        seen
+    }
+
+    pub fn graph(&self) -> (Graph<String, ()>, HashMap<String, NodeIndex>) {
+        let mut graph = Graph::new();
+        let mut nodes = HashMap::new();
+        let mut names: Vec<&String> = self.0.keys().collect();
+        names.sort();
+        for name in names {
+            let tech_node = *nodes
+                .entry(name.clone())
+                .or_insert_with(|| graph.add_node(name.clone()));
+            for prereq in self.0[name].prerequisites.iter() {
+                let prereq_node = *nodes
+                    .entry(prereq.clone())
+                    .or_insert_with(|| graph.add_node(prereq.clone()));
+                graph.update_edge(prereq_node, tech_node, ());
+            }
+        }
+        (graph, nodes)
+    }
+
+    /// Prerequisite edges with every edge implied by a longer chain removed, so that
+    /// e.g. `automation -> logistics-2` disappears when `logistics-2` already requires
+    /// `logistics` which requires `automation`.
+    pub fn reduced_edges(&self) -> Result<Vec<(String, String)>, String> {
+        let (graph, _) = self.graph();
+        let order = toposort(&graph, None).map_err(|c| {
+            format!(
+                "Technology prerequisites contain a cycle at {}",
+                graph[c.node_id()]
+            )
+        })?;
+        let (adjacency, _) = dag_to_toposorted_adjacency_list::<_, u32>(&graph, &order);
+        let (reduction, _) = dag_transitive_reduction_closure(&adjacency);
+        let mut edges = Vec::new();
+        for from in reduction.node_indices() {
+            for to in (&reduction).neighbors(from) {
+                edges.push((
+                    graph[order[from as usize]].clone(),
+                    graph[order[to as usize]].clone(),
+                ));
+            }
+        }
+        edges.sort();
+        Ok(edges)
+    }
+
+    /// Length of the longest prerequisite chain leading to each technology.
+    pub fn tiers(&self) -> Result<HashMap<String, usize>, String> {
+        let (graph, _) = self.graph();
+        let order = toposort(&graph, None).map_err(|c| {
+            format!(
+                "Technology prerequisites contain a cycle at {}",
+                graph[c.node_id()]
+            )
+        })?;
+        let mut tiers = HashMap::new();
+        for node in order {
+            let tier = graph
+                .neighbors_directed(node, petgraph::Direction::Incoming)
+                .map(|pre| tiers[&graph[pre]] + 1)
+                .max()
+                .unwrap_or(0);
+            tiers.insert(graph[node].clone(), tier);
+        }
+        Ok(tiers)
+    }
+
+    /// Renders the tree as graphviz source. Nodes are colored by their most advanced
+    /// science pack and labelled with their cost, technologies of equal tier share a rank,
+    /// and if `target` is given its prerequisites are drawn inside a highlighted cluster.
+    pub fn write_dot<W: Write>(
+        &self,
+        f: &mut W,
+        target: Option<&str>,
+    ) -> Result<(), Box<dyn Error>> {
+        let edges = self.reduced_edges()?;
+        let tiers = self.tiers()?;
+        let highlighted = target.map(|t| self.ancestors(t)).unwrap_or_default();
+
+        let mut by_tier = BTreeMap::<usize, (Vec<&String>, Vec<&String>)>::new();
+        for (name, tier) in tiers.iter() {
+            let (on_path, off_path) = by_tier.entry(*tier).or_default();
+            if highlighted.contains(name) {
+                on_path.push(name);
+            } else {
+                off_path.push(name);
+            }
+        }
+
+        writeln!(f, "digraph technology {{")?;
+        writeln!(f, "rankdir = \"LR\"")?;
+        writeln!(f, "node [shape=box, style=filled, fontsize=10]")?;
+
+        let mut names: Vec<&String> = tiers.keys().collect();
+        names.sort();
+        for name in names {
+            let (color, label) = match self.0.get(name) {
+                Some(tech) => (
+                    tech.highest_science_pack()
+                        .map_or("#dddddd", |i| SCIENCE_PACKS[i].1),
+                    format!("{}\\n{}", name, tech.cost_label()),
+                ),
+                None => ("#dddddd", name.clone()),
+            };
+            let border = if highlighted.contains(name) {
+                ", penwidth=3"
+            } else {
+                ""
+            };
+            writeln!(
+                f,
+                "\"{}\" [label=\"{}\", fillcolor=\"{}\"{}]",
+                name, label, color, border
+            )?;
+        }
+
+        let write_ranks = |f: &mut W, groups: Vec<&Vec<&String>>| -> std::io::Result<()> {
+            for group in groups.into_iter().filter(|g| !g.is_empty()) {
+                let mut group = group.clone();
+                group.sort();
+                write!(f, "{{ rank=same;")?;
+                for name in group {
+                    write!(f, " \"{}\";", name)?;
+                }
+                writeln!(f, " }}")?;
+            }
+            Ok(())
+        };
+
+        if let Some(target) = target {
+            writeln!(f, "subgraph cluster_target {{")?;
+            writeln!(f, "label = \"Path to {}\"", target)?;
+            writeln!(f, "style = \"rounded,filled\"")?;
+            writeln!(f, "fillcolor = \"#fff8e0\"")?;
+            write_ranks(f, by_tier.values().map(|(on, _)| on).collect())?;
+            writeln!(f, "}}")?;
+        }
+        write_ranks(f, by_tier.values().map(|(_, off)| off).collect())?;
+
+        for (from, to) in edges {
+            let style = if highlighted.contains(&from) && highlighted.contains(&to) {
+                " [color=\"#d08000\", penwidth=2]"
+            } else {
+                ""
+            };
+            writeln!(f, "\"{}\" -> \"{}\"{}", from, to, style)?;
+        }
+        writeln!(f, "}}")?;
+        Ok(())
+    }
+}
+
+#[test]
+fn tech_tree_dot() -> Result<(), Box<dyn Error>> {
+    let lua = r#"data:extend({
+        { type = "technology", name = "automation",
+          effects = { { type = "unlock-recipe", recipe = "assembling-machine-1" } },
+          unit = { count = 10, ingredients = { { "automation-science-pack", 1 } }, time = 10 } },
+        { type = "technology", name = "logistics", prerequisites = { "automation" },
+          unit = { count = 50, ingredients = { { "automation-science-pack", 1 } }, time = 15 } },
+        { type = "technology", name = "logistics-2", prerequisites = { "logistics", "automation" },
+          unit = { count = 200, ingredients = { { "automation-science-pack", 1 }, { "logistic-science-pack", 1 } }, time = 30 } },
+        { type = "technology", name = "turrets",
+          unit = { count = 10, ingredients = { { "automation-science-pack", 1 } }, time = 10 } }
+    })
+    data:extend({ { type = "technology", prerequisites = { "turrets" } } })"#;
+    let mut ctx = LuaContext::new();
+    ctx.parse_all::<()>(lua).map_err(|e| format!("{:?}", e))?;
+    let (tree, skipped) = TechTree::from_context(ctx);
+    assert_eq!(tree.0.len(), 4);
+    assert_eq!(skipped.len(), 1);
+
+    assert_eq!(
+        tree.reduced_edges()?,
+        vec![
+            ("automation".to_string(), "logistics".to_string()),
+            ("logistics".to_string(), "logistics-2".to_string()),
+        ]
+    );
+    assert_eq!(tree.tiers()?["logistics-2"], 2);
+    assert_eq!(tree.tiers()?["turrets"], 0);
+    assert_eq!(tree.0["logistics-2"].highest_science_pack(), Some(1));
+    assert_eq!(
+        tree.unlocking("assembling-machine-1").map(|t| &*t.name),
+        Some("automation")
+    );
+
+    let mut dot = Vec::new();
+    tree.write_dot(&mut dot, Some("logistics"))?;
+    let dot = String::from_utf8(dot)?;
+    assert!(dot.contains("subgraph cluster_target"));
+    assert!(dot.contains("\"automation\" -> \"logistics\" [color"));
+    assert!(!dot.contains("\"automation\" -> \"logistics-2\""));
+    assert!(dot.contains("fillcolor=\"#a6e3a6\""));
+    Ok(())
+}